@@ -0,0 +1,276 @@
+// Registers `then` handlers on one pending promise from several threads at
+// once, then settles it. Each round runs against the crate's promise and
+// against `locked::Promise`, a copy of the design it replaced, so the numbers
+// can be compared on the machine at hand. Run with `cargo bench`.
+
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const HANDLERS_PER_THREAD: usize = 50_000;
+const ROUNDS: usize = 5;
+
+// The two designs, reduced to what the benchmark calls.
+trait Bench: Clone + Send + 'static {
+    fn pending() -> (Self, Box<dyn FnOnce(u64)>);
+    fn then_add_one(&self) -> Self;
+    fn value(&self) -> Option<u64>;
+}
+
+impl Bench for promise_rs::Promise<u64, String> {
+    fn pending() -> (Self, Box<dyn FnOnce(u64)>) {
+        let (promise, resolver, _) = promise_rs::Promise::with_resolvers();
+        (promise, Box::new(move |value| resolver.resolve(value)))
+    }
+
+    fn then_add_one(&self) -> Self {
+        self.then(|value| Ok(value + 1), Err)
+    }
+
+    fn value(&self) -> Option<u64> {
+        self.try_get().and_then(Result::ok)
+    }
+}
+
+// The promise state before it moved into a single core: the value, status,
+// handlers and wakers each sat behind their own mutex, and every handler was
+// three boxed callbacks.
+mod locked {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::{Arc, Mutex, Weak};
+    use std::task::Waker;
+
+    use promise_rs::{CancellationToken, Settled, Status};
+
+    type Shared<T> = Arc<Mutex<Option<T>>>;
+    type WeakShared<T> = Weak<Mutex<Option<T>>>;
+
+    struct Handler {
+        on_fulfilled: Box<dyn FnOnce(u64) + Send>,
+        on_rejected: Box<dyn FnOnce(String) + Send>,
+        on_cancelled: Box<dyn FnOnce() + Send>,
+    }
+
+    impl Handler {
+        fn call(self, settled: Settled<u64, String>) {
+            match settled {
+                Settled::Fulfilled(value) => (self.on_fulfilled)(value),
+                Settled::Rejected(reason) => (self.on_rejected)(reason),
+                Settled::Cancelled => (self.on_cancelled)(),
+            }
+        }
+    }
+
+    #[derive(Clone)]
+    pub struct Promise {
+        slots: Slots,
+        token: CancellationToken,
+    }
+
+    #[derive(Clone)]
+    struct Slots {
+        value: Shared<Settled<u64, String>>,
+        status: Shared<Status>,
+        handlers: Shared<Vec<Handler>>,
+        wakers: Shared<Vec<Waker>>,
+    }
+
+    struct WeakSlots {
+        value: WeakShared<Settled<u64, String>>,
+        status: WeakShared<Status>,
+        handlers: WeakShared<Vec<Handler>>,
+        wakers: WeakShared<Vec<Waker>>,
+    }
+
+    impl WeakSlots {
+        fn upgrade(&self) -> Option<Slots> {
+            Some(Slots {
+                value: self.value.upgrade()?,
+                status: self.status.upgrade()?,
+                handlers: self.handlers.upgrade()?,
+                wakers: self.wakers.upgrade()?,
+            })
+        }
+    }
+
+    impl Slots {
+        fn try_settle(&self, settled: Settled<u64, String>) -> bool {
+            let status = match settled {
+                Settled::Fulfilled(_) => Status::Fulfilled,
+                Settled::Rejected(_) => Status::Rejected,
+                Settled::Cancelled => Status::Cancelled,
+            };
+            let mut handlers_guard = self.handlers.lock().unwrap();
+            let Some(handlers) = handlers_guard.take() else {
+                return false;
+            };
+            *self.value.lock().unwrap() = Some(settled.clone());
+            *self.status.lock().unwrap() = Some(status);
+            drop(handlers_guard);
+            if let Some(wakers) = self.wakers.lock().unwrap().take() {
+                wakers.into_iter().for_each(Waker::wake);
+            }
+            for handler in handlers {
+                handler.call(settled.clone());
+            }
+            true
+        }
+
+        fn register(&self, handler: Handler) {
+            let mut handlers_guard = self.handlers.lock().unwrap();
+            if let Some(handlers) = handlers_guard.as_mut() {
+                handlers.push(handler);
+                return;
+            }
+            drop(handlers_guard);
+            let settled = self.value.lock().unwrap().clone();
+            if let Some(settled) = settled {
+                handler.call(settled);
+            }
+        }
+    }
+
+    impl Promise {
+        pub fn pending_with(token: CancellationToken) -> Promise {
+            let slots = Slots {
+                value: Arc::new(Mutex::new(None)),
+                status: Arc::new(Mutex::new(Some(Status::Pending))),
+                handlers: Arc::new(Mutex::new(Some(Vec::new()))),
+                wakers: Arc::new(Mutex::new(Some(Vec::new()))),
+            };
+            let weak = WeakSlots {
+                value: Arc::downgrade(&slots.value),
+                status: Arc::downgrade(&slots.status),
+                handlers: Arc::downgrade(&slots.handlers),
+                wakers: Arc::downgrade(&slots.wakers),
+            };
+            token.on_cancel(move || {
+                if let Some(slots) = weak.upgrade() {
+                    slots.try_settle(Settled::Cancelled);
+                }
+            });
+            Promise { slots, token }
+        }
+
+        pub fn try_settle(&self, settled: Settled<u64, String>) -> bool {
+            self.slots.try_settle(settled)
+        }
+
+        fn on_settled<F>(&self, f: F)
+        where
+            F: FnOnce(Settled<u64, String>) + Clone + Send + 'static,
+        {
+            let on_fulfilled = f.clone();
+            let on_rejected = f.clone();
+            self.slots.register(Handler {
+                on_fulfilled: Box::new(move |value| on_fulfilled(Settled::Fulfilled(value))),
+                on_rejected: Box::new(move |reason| on_rejected(Settled::Rejected(reason))),
+                on_cancelled: Box::new(move || f(Settled::Cancelled)),
+            });
+        }
+
+        pub fn then_add_one(&self) -> Promise {
+            let promise = Promise::pending_with(self.token.child());
+            let promise_settled = promise.clone();
+            self.on_settled(move |settled| {
+                let result = match settled {
+                    Settled::Fulfilled(value) => {
+                        panic::catch_unwind(AssertUnwindSafe(|| value + 1))
+                            .map_err(|_| "panicked".to_string())
+                    }
+                    Settled::Rejected(reason) => Err(reason),
+                    Settled::Cancelled => {
+                        promise_settled.try_settle(Settled::Cancelled);
+                        return;
+                    }
+                };
+                promise_settled.try_settle(result.into());
+            });
+            promise
+        }
+
+        pub fn value(&self) -> Option<u64> {
+            match self.slots.value.lock().unwrap().as_ref() {
+                Some(Settled::Fulfilled(value)) => Some(*value),
+                _ => None,
+            }
+        }
+    }
+}
+
+impl Bench for locked::Promise {
+    fn pending() -> (Self, Box<dyn FnOnce(u64)>) {
+        let promise = locked::Promise::pending_with(promise_rs::CancellationToken::new());
+        let resolver = promise.clone();
+        let resolve = move |value| {
+            resolver.try_settle(promise_rs::Settled::Fulfilled(value));
+        };
+        (promise, Box::new(resolve))
+    }
+
+    fn then_add_one(&self) -> Self {
+        locked::Promise::then_add_one(self)
+    }
+
+    fn value(&self) -> Option<u64> {
+        locked::Promise::value(self)
+    }
+}
+
+fn round<P: Bench>(threads: usize) -> (Duration, Duration) {
+    let (promise, resolve) = P::pending();
+    let barrier = Arc::new(Barrier::new(threads + 1));
+    let workers: Vec<_> = (0..threads)
+        .map(|_| {
+            let promise = promise.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                let derived: Vec<_> = (0..HANDLERS_PER_THREAD)
+                    .map(|_| promise.then_add_one())
+                    .collect();
+                barrier.wait();
+                derived
+            })
+        })
+        .collect();
+
+    barrier.wait();
+    let started = Instant::now();
+    barrier.wait();
+    let registered = started.elapsed();
+
+    let started = Instant::now();
+    resolve(1);
+    let settled = started.elapsed();
+
+    for worker in workers {
+        let derived = worker.join().unwrap();
+        assert_eq!(derived.last().unwrap().value(), Some(2));
+    }
+    (registered, settled)
+}
+
+fn report<P: Bench>(design: &str, threads: usize) {
+    let (registered, settled) = (0..ROUNDS).map(|_| round::<P>(threads)).min().unwrap();
+    let handlers = (threads * HANDLERS_PER_THREAD) as f64;
+    println!(
+        "{:<7}  {:>7}  {:>19.1}  {:>17.1}",
+        design,
+        threads,
+        registered.as_nanos() as f64 / handlers,
+        settled.as_nanos() as f64 / handlers,
+    );
+}
+
+fn main() {
+    println!(
+        "{} cores available",
+        thread::available_parallelism().map_or(1, |cores| cores.get())
+    );
+    println!("design   threads  register ns/handler  settle ns/handler");
+    for threads in [1, 2, 4, 8] {
+        report::<locked::Promise>("locked", threads);
+        report::<promise_rs::Promise<u64, String>>("core", threads);
+    }
+}
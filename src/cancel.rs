@@ -0,0 +1,141 @@
+use std::fmt;
+use std::sync::{Arc, Mutex, Weak};
+
+type Callback = Box<dyn FnOnce() + Send>;
+
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    cancelled: bool,
+    callbacks: Vec<Callback>,
+    children: Vec<Weak<Inner>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn child(&self) -> CancellationToken {
+        let child = CancellationToken::new();
+        let mut state = self.inner.state.lock().unwrap();
+        if state.cancelled {
+            drop(state);
+            child.cancel();
+            return child;
+        }
+        if state.children.len() == state.children.capacity() {
+            state.children.retain(|child| child.strong_count() > 0);
+        }
+        state.children.push(Arc::downgrade(&child.inner));
+        child
+    }
+
+    pub fn cancel(&self) {
+        let mut pending = vec![self.inner.clone()];
+        while let Some(inner) = pending.pop() {
+            let mut state = inner.state.lock().unwrap();
+            if state.cancelled {
+                continue;
+            }
+            state.cancelled = true;
+            let callbacks = std::mem::take(&mut state.callbacks);
+            let children = std::mem::take(&mut state.children);
+            drop(state);
+            for callback in callbacks {
+                callback();
+            }
+            pending.extend(children.iter().filter_map(Weak::upgrade));
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.state.lock().unwrap().cancelled
+    }
+
+    pub fn on_cancel<F>(&self, callback: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut state = self.inner.state.lock().unwrap();
+        if state.cancelled {
+            drop(state);
+            callback();
+        } else {
+            state.callbacks.push(Box::new(callback));
+        }
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn cancelling_a_parent_cancels_its_descendants_once() {
+        let parent = CancellationToken::new();
+        let child = parent.child();
+        let grandchild = child.child();
+        let calls = Arc::new(AtomicUsize::new(0));
+        for token in [&parent, &child, &grandchild] {
+            let calls = calls.clone();
+            token.on_cancel(move || {
+                calls.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        parent.cancel();
+        parent.cancel();
+        assert!(grandchild.is_cancelled());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn cancelling_a_child_leaves_its_parent_running() {
+        let parent = CancellationToken::new();
+        let child = parent.child();
+        child.cancel();
+        assert!(child.is_cancelled());
+        assert!(!parent.is_cancelled());
+    }
+
+    #[test]
+    fn late_children_and_callbacks_see_the_cancellation() {
+        let parent = CancellationToken::new();
+        parent.cancel();
+        assert!(parent.child().is_cancelled());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        parent.on_cancel(move || {
+            counted.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn dropped_children_are_pruned() {
+        let parent = CancellationToken::new();
+        for _ in 0..1_000 {
+            drop(parent.child());
+        }
+        assert!(parent.inner.state.lock().unwrap().children.len() <= 64);
+    }
+}
@@ -0,0 +1,175 @@
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+
+pub type Task = Box<dyn FnOnce() + Send + 'static>;
+
+pub trait Executor: Send + Sync {
+    fn execute(&self, task: Task);
+}
+
+pub struct ThreadPool {
+    sender: SyncSender<Task>,
+    workers: usize,
+}
+
+pub struct ThreadPoolBuilder {
+    workers: usize,
+    queue_bound: usize,
+    thread_name: String,
+}
+
+impl Default for ThreadPoolBuilder {
+    fn default() -> Self {
+        ThreadPoolBuilder {
+            workers: thread::available_parallelism().map_or(4, |n| n.get().max(4)),
+            queue_bound: 1024,
+            thread_name: "promise-worker".to_string(),
+        }
+    }
+}
+
+impl ThreadPoolBuilder {
+    pub fn new() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::default()
+    }
+
+    pub fn workers(mut self, workers: usize) -> ThreadPoolBuilder {
+        self.workers = workers.max(1);
+        self
+    }
+
+    pub fn queue_bound(mut self, queue_bound: usize) -> ThreadPoolBuilder {
+        self.queue_bound = queue_bound;
+        self
+    }
+
+    pub fn thread_name<S: Into<String>>(mut self, thread_name: S) -> ThreadPoolBuilder {
+        self.thread_name = thread_name.into();
+        self
+    }
+
+    pub fn build(self) -> io::Result<ThreadPool> {
+        let (sender, receiver) = mpsc::sync_channel::<Task>(self.queue_bound);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..self.workers {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("{}-{}", self.thread_name, index))
+                .spawn(move || work(&receiver))?;
+        }
+        Ok(ThreadPool {
+            sender,
+            workers: self.workers,
+        })
+    }
+}
+
+fn work(receiver: &Mutex<Receiver<Task>>) {
+    loop {
+        let task = match receiver.lock().unwrap().recv() {
+            Ok(task) => task,
+            Err(_) => return,
+        };
+        let _ = panic::catch_unwind(AssertUnwindSafe(task));
+    }
+}
+
+impl ThreadPool {
+    pub fn builder() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::new()
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    fn try_execute(&self, task: Task) -> Result<(), Task> {
+        self.sender.try_send(task).map_err(|error| match error {
+            TrySendError::Full(task) | TrySendError::Disconnected(task) => task,
+        })
+    }
+}
+
+impl Executor for ThreadPool {
+    fn execute(&self, task: Task) {
+        if let Err(mpsc::SendError(task)) = self.sender.send(task) {
+            task();
+        }
+    }
+}
+
+pub fn default_executor() -> &'static ThreadPool {
+    static DEFAULT: OnceLock<ThreadPool> = OnceLock::new();
+    DEFAULT.get_or_init(|| {
+        ThreadPool::builder()
+            .build()
+            .expect("failed to spawn the default promise thread pool")
+    })
+}
+
+// Hands `task` to the default executor without ever blocking the caller, for
+// threads such as the timer's that every other promise depends on. When the
+// queue is full the task gets a thread of its own.
+pub(crate) fn spawn(task: Task) {
+    if let Err(task) = default_executor().try_execute(task) {
+        thread::Builder::new()
+            .name("promise-overflow".to_string())
+            .spawn(task)
+            .expect("failed to spawn a promise overflow thread");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn runs_tasks_on_named_workers() {
+        let pool = ThreadPool::builder()
+            .workers(2)
+            .thread_name("test-worker")
+            .build()
+            .unwrap();
+        assert_eq!(pool.workers(), 2);
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..8 {
+            let sender = sender.clone();
+            pool.execute(Box::new(move || {
+                let name = thread::current().name().map(str::to_string);
+                sender.send(name).unwrap();
+            }));
+        }
+        for _ in 0..8 {
+            let name = receiver.recv_timeout(Duration::from_secs(2)).unwrap();
+            assert!(name.unwrap().starts_with("test-worker-"));
+        }
+    }
+
+    #[test]
+    fn a_panicking_task_does_not_take_down_its_worker() {
+        let pool = ThreadPool::builder().workers(1).build().unwrap();
+        pool.execute(Box::new(|| panic!("task failed")));
+        let (sender, receiver) = mpsc::channel();
+        pool.execute(Box::new(move || sender.send(()).unwrap()));
+        assert!(receiver.recv_timeout(Duration::from_secs(2)).is_ok());
+    }
+
+    #[test]
+    fn promises_run_on_the_given_executor() {
+        let pool = ThreadPool::builder()
+            .workers(1)
+            .thread_name("own-pool")
+            .build()
+            .unwrap();
+        let name = crate::Promise::<String, String>::new_on(&pool, |resolve, _| {
+            resolve(thread::current().name().unwrap().to_string())
+        });
+        assert_eq!(name.a_await(), Ok("own-pool-0".to_string()));
+    }
+}
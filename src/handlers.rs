@@ -0,0 +1,366 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::Settled;
+
+pub(crate) type Handler<T, E> = Box<dyn FnOnce(Settled<T, E>) + Send>;
+
+type Job = Box<dyn FnOnce()>;
+
+// How many handler calls may nest on one thread before further ones are queued.
+const MAX_DEPTH: usize = 64;
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static QUEUE: RefCell<VecDeque<Job>> = const { RefCell::new(VecDeque::new()) };
+}
+
+// Handlers settle further promises, so calling them straight from the settling
+// promise uses a few stack frames per link of a chain. Calls run inline up to
+// `MAX_DEPTH` levels deep; deeper ones are queued and run once the level above
+// returns. While anything is queued, new calls queue behind it, so handlers
+// still run in the order they were scheduled.
+pub(crate) fn run<T, E>(handlers: Vec<Handler<T, E>>, settled: Settled<T, E>)
+where
+    T: Clone + 'static,
+    E: Clone + 'static,
+{
+    if handlers.is_empty() {
+        return;
+    }
+    defer(Box::new(move || {
+        for handler in handlers {
+            handler(settled.clone());
+        }
+    }));
+}
+
+fn defer(job: Job) {
+    let inline = DEPTH.get() < MAX_DEPTH && QUEUE.with(|queue| queue.borrow().is_empty());
+    if !inline {
+        QUEUE.with(|queue| queue.borrow_mut().push_back(job));
+        return;
+    }
+    let panicked = call(job).err();
+    let drained = drain();
+    if let Some(payload) = panicked.or(drained) {
+        panic::resume_unwind(payload);
+    }
+}
+
+// Runs the jobs queued on this thread. A blocking wait calls this before it
+// parks, since the promise it waits for may be settled by one of them.
+pub(crate) fn run_queued() {
+    if let Some(payload) = drain() {
+        panic::resume_unwind(payload);
+    }
+}
+
+// A job that unwinds must not strand the ones behind it, which belong to
+// unrelated promises, so every job runs and the first panic is handed back.
+fn drain() -> Option<Box<dyn Any + Send>> {
+    let mut panicked = None;
+    while let Some(job) = QUEUE.with(|queue| queue.borrow_mut().pop_front()) {
+        if let Err(payload) = call(job) {
+            panicked.get_or_insert(payload);
+        }
+    }
+    panicked
+}
+
+fn call(job: Job) -> Result<(), Box<dyn Any + Send>> {
+    let depth = DEPTH.get();
+    DEPTH.set(depth + 1);
+    let result = panic::catch_unwind(AssertUnwindSafe(job));
+    DEPTH.set(depth);
+    result
+}
+
+struct Node<T: 'static, E: 'static> {
+    handler: Handler<T, E>,
+    next: *mut Node<T, E>,
+}
+
+// A lock-free stack of handlers that is closed exactly once, when the promise
+// settles. Handlers are only ever removed all at once by `close`, so pushes
+// cannot run into ABA problems. The tests below also run under
+// `cargo +nightly miri test --lib handlers::`.
+pub(crate) struct HandlerList<T: 'static, E: 'static> {
+    head: AtomicPtr<Node<T, E>>,
+}
+
+// SAFETY: the nodes only hold `Send` handlers, and every access to them goes
+// through the atomic head.
+unsafe impl<T, E> Send for HandlerList<T, E> {}
+unsafe impl<T, E> Sync for HandlerList<T, E> {}
+
+impl<T, E> HandlerList<T, E> {
+    // Never a valid allocation, so it cannot collide with a real node.
+    fn closed() -> *mut Node<T, E> {
+        ptr::dangling_mut()
+    }
+
+    pub(crate) fn new() -> HandlerList<T, E> {
+        HandlerList {
+            head: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    // Hands the handler back if the list has already been closed.
+    pub(crate) fn push(&self, handler: Handler<T, E>) -> Result<(), Handler<T, E>> {
+        let mut head = self.head.load(Ordering::Acquire);
+        if head == Self::closed() {
+            return Err(handler);
+        }
+        let node = Box::into_raw(Box::new(Node {
+            handler,
+            next: head,
+        }));
+        loop {
+            match self
+                .head
+                .compare_exchange_weak(head, node, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return Ok(()),
+                Err(current) if current == Self::closed() => {
+                    // SAFETY: the node was never published, so we still own it.
+                    let node = unsafe { Box::from_raw(node) };
+                    return Err(node.handler);
+                }
+                Err(current) => {
+                    head = current;
+                    // SAFETY: as above, the node is still private to us.
+                    unsafe { (*node).next = head };
+                }
+            }
+        }
+    }
+
+    // Closes the list and returns its handlers in registration order. Only the
+    // first call gets them; later pushes are refused.
+    pub(crate) fn close(&self) -> Vec<Handler<T, E>> {
+        let mut node = self.head.swap(Self::closed(), Ordering::AcqRel);
+        if node == Self::closed() {
+            return Vec::new();
+        }
+        let mut handlers = Vec::new();
+        while !node.is_null() {
+            // SAFETY: the swap unlinked the whole stack, so every node on it
+            // is now exclusively ours.
+            let boxed = unsafe { Box::from_raw(node) };
+            node = boxed.next;
+            handlers.push(boxed.handler);
+        }
+        handlers.reverse();
+        handlers
+    }
+}
+
+// Pending handlers own the promises they would have settled, so dropping a
+// long unsettled chain would recurse just like settling it. The drop is
+// deferred the same way.
+impl<T, E> Drop for HandlerList<T, E> {
+    fn drop(&mut self) {
+        let handlers = self.close();
+        if !handlers.is_empty() {
+            defer(Box::new(move || drop(handlers)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Barrier, Mutex};
+    use std::thread;
+
+    use super::*;
+
+    const THREADS: usize = 4;
+    const PUSHES: usize = if cfg!(miri) { 20 } else { 2_000 };
+
+    fn counting(calls: &Arc<AtomicUsize>) -> Handler<usize, ()> {
+        let calls = calls.clone();
+        Box::new(move |_| {
+            calls.fetch_add(1, Ordering::SeqCst);
+        })
+    }
+
+    #[test]
+    fn close_returns_handlers_in_registration_order() {
+        let list = HandlerList::<usize, ()>::new();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for index in 0..3 {
+            let order = order.clone();
+            assert!(list
+                .push(Box::new(move |_| order.lock().unwrap().push(index)))
+                .is_ok());
+        }
+        for handler in list.close() {
+            handler(Settled::Fulfilled(0));
+        }
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2]);
+        assert!(list.close().is_empty());
+    }
+
+    #[test]
+    fn push_after_close_hands_the_handler_back() {
+        let list = HandlerList::<usize, ()>::new();
+        let calls = Arc::new(AtomicUsize::new(0));
+        assert!(list.close().is_empty());
+        let handler = list.push(counting(&calls)).unwrap_err();
+        handler(Settled::Cancelled);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn concurrent_pushes_are_all_closed_over() {
+        let list = Arc::new(HandlerList::<usize, ()>::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let pushers: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let (list, calls) = (list.clone(), calls.clone());
+                thread::spawn(move || {
+                    for _ in 0..PUSHES {
+                        assert!(list.push(counting(&calls)).is_ok());
+                    }
+                })
+            })
+            .collect();
+        pushers
+            .into_iter()
+            .for_each(|pusher| pusher.join().unwrap());
+        let handlers = list.close();
+        assert_eq!(handlers.len(), THREADS * PUSHES);
+        handlers
+            .into_iter()
+            .for_each(|handler| handler(Settled::Fulfilled(0)));
+        assert_eq!(calls.load(Ordering::SeqCst), THREADS * PUSHES);
+    }
+
+    #[test]
+    fn pushes_racing_close_run_exactly_once() {
+        let list = Arc::new(HandlerList::<usize, ()>::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let barrier = Arc::new(Barrier::new(THREADS + 1));
+        let pushers: Vec<_> = (0..THREADS)
+            .map(|_| {
+                let (list, calls, barrier) = (list.clone(), calls.clone(), barrier.clone());
+                thread::spawn(move || {
+                    barrier.wait();
+                    for _ in 0..PUSHES {
+                        if let Err(handler) = list.push(counting(&calls)) {
+                            handler(Settled::Fulfilled(0));
+                        }
+                    }
+                })
+            })
+            .collect();
+        barrier.wait();
+        for handler in list.close() {
+            handler(Settled::Fulfilled(0));
+        }
+        pushers
+            .into_iter()
+            .for_each(|pusher| pusher.join().unwrap());
+        assert_eq!(calls.load(Ordering::SeqCst), THREADS * PUSHES);
+        assert!(list.close().is_empty());
+    }
+
+    #[test]
+    fn dropping_an_open_list_drops_its_handlers() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let list = HandlerList::<usize, ()>::new();
+        for _ in 0..3 {
+            assert!(list.push(counting(&calls)).is_ok());
+        }
+        drop(list);
+        assert_eq!(Arc::strong_count(&calls), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    fn nest(levels: usize, innermost: Box<dyn FnOnce() + Send>) {
+        if levels == 0 {
+            return innermost();
+        }
+        let handler: Handler<usize, ()> = Box::new(move |_| nest(levels - 1, innermost));
+        run(vec![handler], Settled::Fulfilled(0));
+    }
+
+    fn recording(order: &Arc<Mutex<Vec<&'static str>>>, name: &'static str) -> Handler<usize, ()> {
+        let order = order.clone();
+        Box::new(move |_| order.lock().unwrap().push(name))
+    }
+
+    #[test]
+    fn nested_runs_below_the_limit_run_inline() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let (nested, outer) = (recording(&order, "nested"), order.clone());
+        let handler: Handler<usize, ()> = Box::new(move |_| {
+            run(vec![nested], Settled::Fulfilled(0));
+            outer.lock().unwrap().push("outer");
+        });
+        run(vec![handler], Settled::Fulfilled(0));
+        assert_eq!(*order.lock().unwrap(), vec!["nested", "outer"]);
+    }
+
+    #[test]
+    fn runs_past_the_limit_are_queued_in_order() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let (first, second, outer) = (
+            recording(&order, "first"),
+            recording(&order, "second"),
+            order.clone(),
+        );
+        nest(
+            MAX_DEPTH,
+            Box::new(move || {
+                run(vec![first], Settled::Fulfilled(0));
+                run(vec![second], Settled::Fulfilled(0));
+                outer.lock().unwrap().push("outer");
+                assert_eq!(DEPTH.get(), MAX_DEPTH);
+            }),
+        );
+        assert_eq!(*order.lock().unwrap(), vec!["outer", "first", "second"]);
+        assert_eq!(DEPTH.get(), 0);
+    }
+
+    #[test]
+    fn run_queued_runs_what_is_waiting() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let (queued, outer) = (recording(&order, "queued"), order.clone());
+        nest(
+            MAX_DEPTH,
+            Box::new(move || {
+                run(vec![queued], Settled::Fulfilled(0));
+                run_queued();
+                outer.lock().unwrap().push("outer");
+            }),
+        );
+        assert_eq!(*order.lock().unwrap(), vec!["queued", "outer"]);
+    }
+
+    #[test]
+    fn a_panicking_job_does_not_strand_the_queue() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let after = recording(&order, "after");
+        let panicking: Handler<usize, ()> = Box::new(|_| panic!("handler panicked"));
+        let unwound = panic::catch_unwind(AssertUnwindSafe(|| {
+            nest(
+                MAX_DEPTH,
+                Box::new(move || {
+                    run(vec![panicking], Settled::Fulfilled(0));
+                    run(vec![after], Settled::Fulfilled(0));
+                }),
+            )
+        }));
+        assert!(unwound.is_err());
+        assert_eq!(*order.lock().unwrap(), vec!["after"]);
+        assert_eq!(DEPTH.get(), 0);
+        assert!(QUEUE.with(|queue| queue.borrow().is_empty()));
+    }
+}
@@ -1,205 +1,1709 @@
-use std::sync::{Arc, Mutex};
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::task::{Context, Poll, Wake, Waker};
 use std::thread;
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone, PartialEq)]
+pub mod cancel;
+pub mod executor;
+mod handlers;
+mod limit;
+mod retry;
+mod set;
+mod timer;
+
+use handlers::{Handler, HandlerList};
+use timer::{fire_at, timer};
+
+pub use cancel::CancellationToken;
+pub use executor::{default_executor, Executor, ThreadPool, ThreadPoolBuilder};
+pub use retry::{Backoff, RetryPolicy};
+pub use set::{NextSettled, PromiseSet};
+pub use timer::Interval;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Status {
     Pending,
     Fulfilled,
     Rejected,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Settled<T, E> {
+    Fulfilled(T),
+    Rejected(E),
+    Cancelled,
+}
+
+impl<T, E> Settled<T, E> {
+    pub fn into_result(self) -> Result<T, E>
+    where
+        E: From<Cancelled>,
+    {
+        match self {
+            Settled::Fulfilled(value) => Ok(value),
+            Settled::Rejected(reason) => Err(reason),
+            Settled::Cancelled => Err(E::from(Cancelled)),
+        }
+    }
+}
+
+impl<T, E> From<Result<T, E>> for Settled<T, E> {
+    fn from(result: Result<T, E>) -> Settled<T, E> {
+        match result {
+            Ok(value) => Settled::Fulfilled(value),
+            Err(reason) => Settled::Rejected(reason),
+        }
+    }
+}
+
+// Values of `Core::state`. `SETTLING` is only held by the thread that won the
+// transition while it publishes the value.
+const PENDING: u8 = 0;
+const SETTLING: u8 = 1;
+const FULFILLED: u8 = 2;
+const REJECTED: u8 = 3;
+const CANCELLED: u8 = 4;
+
+// Everything a promise shares lives in one allocation. Registering handlers
+// never takes a lock; `slot` is only locked to publish or read the value and
+// by waiters, and cloning the value under it keeps `T: Sync` off the bounds.
+struct Core<T: 'static, E: 'static> {
+    state: AtomicU8,
+    slot: Mutex<Slot<T, E>>,
+    handlers: HandlerList<T, E>,
+    token: CancellationToken,
+}
+
+// Each waiter keeps its own key, so it can replace its waker on every poll and
+// remove it when it stops waiting.
+struct Slot<T, E> {
+    value: Option<Settled<T, E>>,
+    wakers: HashMap<u64, Waker>,
+    next_waker: u64,
+}
+
+impl<T, E> Core<T, E> {
+    fn forget_waker(&self, key: Option<u64>) {
+        if let Some(key) = key {
+            self.slot.lock().unwrap().wakers.remove(&key);
+        }
+    }
+}
+
+pub struct Promise<T: 'static, E: 'static> {
+    core: Arc<Core<T, E>>,
+    waker: Option<u64>,
 }
 
-pub struct Handler {
-    pub resolve: bool,
-    pub handler: Box<dyn Fn(Option<String>) -> Option<String> + Send>,
+struct WeakPromise<T: 'static, E: 'static> {
+    core: Weak<Core<T, E>>,
 }
 
-pub struct Promise {
-    pub value: Arc<Mutex<Option<String>>>,
-    pub status: Arc<Mutex<Option<Status>>>,
-    pub handlers: Arc<Mutex<Option<Vec<Handler>>>>,
-    pub thread: std::thread::JoinHandle<()>,
+impl<T, E> WeakPromise<T, E> {
+    fn upgrade(&self) -> Option<Promise<T, E>> {
+        self.core
+            .upgrade()
+            .map(|core| Promise { core, waker: None })
+    }
+}
+
+pub type StringPromise = Promise<String, String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedOut;
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("promise timed out")
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+impl From<TimedOut> for String {
+    fn from(timed_out: TimedOut) -> String {
+        timed_out.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("promise was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl From<Cancelled> for String {
+    fn from(cancelled: Cancelled) -> String {
+        cancelled.to_string()
+    }
+}
+
+// `cancelled` is set when the aggregate itself was cancelled, in which case
+// no reasons were collected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AggregateError<E> {
+    pub errors: Vec<E>,
+    pub cancelled: bool,
+}
+
+impl<E> fmt::Display for AggregateError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.cancelled {
+            return fmt::Display::fmt(&Cancelled, f);
+        }
+        write!(f, "all {} promises were rejected", self.errors.len())
+    }
+}
+
+impl<E> From<Cancelled> for AggregateError<E> {
+    fn from(_: Cancelled) -> AggregateError<E> {
+        AggregateError {
+            errors: Vec::new(),
+            cancelled: true,
+        }
+    }
 }
 
-impl Promise {
-    pub fn new<F>(executor: F) -> Promise
+impl<E: fmt::Debug> std::error::Error for AggregateError<E> {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Panicked {
+    pub message: String,
+}
+
+impl Panicked {
+    fn from_payload(payload: Box<dyn Any + Send>) -> Panicked {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&'static str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "Box<dyn Any>".to_string(),
+            },
+        };
+        Panicked { message }
+    }
+}
+
+impl fmt::Display for Panicked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "promise panicked: {}", self.message)
+    }
+}
+
+impl std::error::Error for Panicked {}
+
+impl From<Panicked> for String {
+    fn from(panicked: Panicked) -> String {
+        panicked.to_string()
+    }
+}
+
+fn catch_panic<R, F: FnOnce() -> R>(f: F) -> Result<R, Panicked> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(Panicked::from_payload)
+}
+
+type SettleHook = Box<dyn Fn(Status) + Send + Sync>;
+
+static IGNORED_SETTLEMENT_HOOK: RwLock<Option<SettleHook>> = RwLock::new(None);
+
+// Called with the attempted status whenever `resolve`/`reject` is invoked on
+// an already settled promise. Such calls are otherwise ignored.
+pub fn set_ignored_settlement_hook<F>(hook: F)
+where
+    F: Fn(Status) + Send + Sync + 'static,
+{
+    *IGNORED_SETTLEMENT_HOOK.write().unwrap() = Some(Box::new(hook));
+}
+
+fn report_ignored_settlement(status: Status) {
+    if let Some(hook) = IGNORED_SETTLEMENT_HOOK.read().unwrap().as_ref() {
+        hook(status);
+    }
+}
+
+struct ThreadWaker(thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+impl<T, E> Clone for Promise<T, E> {
+    fn clone(&self) -> Self {
+        Promise {
+            core: self.core.clone(),
+            waker: None,
+        }
+    }
+}
+
+impl<T, E> Promise<T, E>
+where
+    T: Clone + Send + 'static,
+    E: Clone + Send + 'static,
+{
+    fn pending() -> Promise<T, E> {
+        Promise::pending_with(CancellationToken::new())
+    }
+
+    fn pending_with(token: CancellationToken) -> Promise<T, E> {
+        let promise = Promise {
+            core: Arc::new(Core {
+                state: AtomicU8::new(PENDING),
+                slot: Mutex::new(Slot {
+                    value: None,
+                    wakers: HashMap::new(),
+                    next_waker: 0,
+                }),
+                handlers: HandlerList::new(),
+                token,
+            }),
+            waker: None,
+        };
+        let weak = promise.downgrade();
+        promise.core.token.on_cancel(move || {
+            if let Some(promise) = weak.upgrade() {
+                promise.try_settle(Settled::Cancelled);
+            }
+        });
+        promise
+    }
+
+    fn derived<U, F>(&self) -> Promise<U, F>
     where
-        F: Send + 'static + Fn(&dyn Fn(Option<String>), &dyn Fn(Option<String>)),
+        U: Clone + Send + 'static,
+        F: Clone + Send + 'static,
     {
-        let result = Arc::new(Mutex::new(None));
-        let result_resolve = result.clone();
-        let result_reject = result.clone();
+        // A value or reason is passed on even if the token was cancelled
+        // after the promise settled.
+        match self.poll_status() {
+            Status::Fulfilled | Status::Rejected => Promise::pending(),
+            _ => Promise::pending_with(self.core.token.child()),
+        }
+    }
 
-        let status = Arc::new(Mutex::new(Some(Status::Pending)));
-        let status_resolve = status.clone();
-        let status_reject = status.clone();
+    fn downgrade(&self) -> WeakPromise<T, E> {
+        WeakPromise {
+            core: Arc::downgrade(&self.core),
+        }
+    }
 
-        let handlers = Arc::new(Mutex::new(Some(Vec::new())));
-        let handlers_resolve = handlers.clone();
-        let handlers_reject = handlers.clone();
+    // Settlement is decided by a single compare-and-swap on the state word.
+    // The winner publishes the value before the final state and only then
+    // closes the handler list, so anyone who finds the list closed or the
+    // state settled also finds the value. Returns the handlers still to run.
+    fn publish(&self, settled: &Settled<T, E>) -> Option<Vec<Handler<T, E>>> {
+        let state = match settled {
+            Settled::Fulfilled(_) => FULFILLED,
+            Settled::Rejected(_) => REJECTED,
+            Settled::Cancelled => CANCELLED,
+        };
+        let core = &*self.core;
+        if core
+            .state
+            .compare_exchange(PENDING, SETTLING, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return None;
+        }
+        let wakers = {
+            let mut slot = core.slot.lock().unwrap();
+            slot.value = Some(settled.clone());
+            std::mem::take(&mut slot.wakers)
+        };
+        core.state.store(state, Ordering::Release);
+        wakers.into_values().for_each(Waker::wake);
+        Some(core.handlers.close())
+    }
 
-        let thread = thread::spawn(move || {
-            let resolve = move |value| {
-                let mut prev_value: Option<String> = value;
-                for handler in handlers_resolve.lock().unwrap().take().unwrap().into_iter() {
-                    let handler: Handler = handler;
-                    if handler.resolve == true {
-                        prev_value = (handler.handler)(prev_value.clone());
-                    }
+    fn try_settle(&self, settled: Settled<T, E>) -> bool {
+        match self.publish(&settled) {
+            Some(handlers) => {
+                handlers::run(handlers, settled);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Settles from the timer thread, which must neither block nor run user
+    // code: waiters are woken right away and the handlers go to the executor.
+    fn settle_detached(&self, result: Result<T, E>) {
+        let settled = Settled::from(result);
+        if let Some(handlers) = self.publish(&settled) {
+            if !handlers.is_empty() {
+                executor::spawn(Box::new(move || handlers::run(handlers, settled)));
+            }
+        }
+    }
+
+    fn settle(&self, result: Result<T, E>) {
+        self.try_settle(Settled::from(result));
+    }
+
+    fn settled(&self) -> Option<Settled<T, E>> {
+        self.core.slot.lock().unwrap().value.clone()
+    }
+
+    fn on_settled<F>(&self, f: F)
+    where
+        F: FnOnce(Settled<T, E>) + Send + 'static,
+    {
+        let handler: Handler<T, E> = Box::new(f);
+        if let Err(handler) = self.core.handlers.push(handler) {
+            // Handlers registered earlier may still be queued on this thread,
+            // so this one goes behind them.
+            let settled = self.settled();
+            handlers::run(
+                vec![handler],
+                settled.expect("a closed handler list implies a settled value"),
+            );
+        }
+    }
+
+    fn poll_settled(&self, key: &mut Option<u64>, cx: &mut Context<'_>) -> Poll<Settled<T, E>> {
+        let mut slot = self.core.slot.lock().unwrap();
+        if let Some(settled) = slot.value.as_ref() {
+            return Poll::Ready(settled.clone());
+        }
+        match *key {
+            Some(key) => {
+                if let Some(waker) = slot.wakers.get_mut(&key) {
+                    waker.clone_from(cx.waker());
                 }
-                let mut result_resolve = result_resolve.lock().unwrap();
-                *result_resolve = prev_value;
-                let mut state_guard = status_resolve.lock().unwrap();
-                let state = state_guard.as_mut().unwrap();
-                *state = Status::Fulfilled;
+            }
+            None => {
+                let next = slot.next_waker;
+                slot.next_waker += 1;
+                slot.wakers.insert(next, cx.waker().clone());
+                *key = Some(next);
+            }
+        }
+        Poll::Pending
+    }
+
+    fn adopt(&self, source: &Promise<T, E>) {
+        let promise = self.clone();
+        source.on_settled(move |settled| match settled {
+            Settled::Cancelled => promise.cancel(),
+            settled => {
+                promise.try_settle(settled);
+            }
+        });
+    }
+
+    // Cancelling `self` cancels every input that is still running.
+    fn cancel_inputs_on_cancel<U, F>(&self, inputs: &[Promise<U, F>])
+    where
+        U: Clone + Send + 'static,
+        F: Clone + Send + 'static,
+    {
+        let inputs: Vec<WeakPromise<U, F>> = inputs.iter().map(Promise::downgrade).collect();
+        self.core.token.on_cancel(move || {
+            inputs
+                .iter()
+                .filter_map(WeakPromise::upgrade)
+                .for_each(|input| input.cancel());
+        });
+    }
+
+    pub fn new<F>(executor: F) -> Promise<T, E>
+    where
+        E: From<Panicked>,
+        F: Send + 'static + FnOnce(&dyn Fn(T), &dyn Fn(E)),
+    {
+        Promise::new_on(default_executor(), executor)
+    }
+
+    pub fn new_on<F>(on: &dyn Executor, executor: F) -> Promise<T, E>
+    where
+        E: From<Panicked>,
+        F: Send + 'static + FnOnce(&dyn Fn(T), &dyn Fn(E)),
+    {
+        Promise::new_cancellable_on(on, move |resolver, rejecter, _| {
+            executor(&|value| resolver.resolve(value), &|reason| {
+                rejecter.reject(reason)
+            })
+        })
+    }
+
+    // The executor gets the owned handles, so it can hand them to callbacks
+    // or adopt another promise through `Resolver::resolve_with`.
+    pub fn new_cancellable<F>(executor: F) -> Promise<T, E>
+    where
+        E: From<Panicked>,
+        F: Send + 'static + FnOnce(&Resolver<T, E>, &Rejecter<T, E>, &CancellationToken),
+    {
+        Promise::new_cancellable_on(default_executor(), executor)
+    }
+
+    pub fn new_cancellable_on<F>(on: &dyn Executor, executor: F) -> Promise<T, E>
+    where
+        E: From<Panicked>,
+        F: Send + 'static + FnOnce(&Resolver<T, E>, &Rejecter<T, E>, &CancellationToken),
+    {
+        let (promise, resolver, rejecter) = Promise::with_resolvers();
+        let promise_panic = promise.clone();
+        let token = promise.core.token.clone();
+
+        on.execute(Box::new(move || {
+            if token.is_cancelled() {
+                return;
+            }
+            if let Err(panicked) = catch_panic(|| executor(&resolver, &rejecter, &token)) {
+                promise_panic.settle(Err(E::from(panicked)));
+            }
+        }));
+
+        promise
+    }
+
+    pub fn with_resolvers() -> (Promise<T, E>, Resolver<T, E>, Rejecter<T, E>) {
+        let promise = Promise::pending();
+        let resolver = Resolver {
+            promise: promise.clone(),
+        };
+        let rejecter = Rejecter {
+            promise: promise.clone(),
+        };
+        (promise, resolver, rejecter)
+    }
+
+    // Does nothing once the promise has settled, so its value, its reason and
+    // the promises derived from it are left alone.
+    pub fn cancel(&self) {
+        if self.is_pending() {
+            self.core.token.cancel();
+        }
+    }
+
+    pub fn token(&self) -> &CancellationToken {
+        &self.core.token
+    }
+
+    pub fn poll_status(&self) -> Status {
+        match self.core.state.load(Ordering::Acquire) {
+            FULFILLED => Status::Fulfilled,
+            REJECTED => Status::Rejected,
+            CANCELLED => Status::Cancelled,
+            _ => Status::Pending,
+        }
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.poll_status() == Status::Pending
+    }
+
+    pub fn is_fulfilled(&self) -> bool {
+        self.poll_status() == Status::Fulfilled
+    }
+
+    pub fn is_rejected(&self) -> bool {
+        self.poll_status() == Status::Rejected
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.poll_status() == Status::Cancelled
+    }
+
+    pub fn try_get(&self) -> Option<Result<T, E>>
+    where
+        E: From<Cancelled>,
+    {
+        self.settled().map(Settled::into_result)
+    }
+
+    pub fn then<U, F, F1, F2>(&self, on_fulfilled: F1, on_rejected: F2) -> Promise<U, F>
+    where
+        U: Clone + Send + 'static,
+        F: Clone + Send + 'static + From<Panicked>,
+        F1: Send + 'static + FnOnce(T) -> Result<U, F>,
+        F2: Send + 'static + FnOnce(E) -> Result<U, F>,
+    {
+        let promise = self.derived();
+        let promise_settled = promise.clone();
+        self.on_settled(move |settled| {
+            let result = match settled {
+                Settled::Fulfilled(value) => catch_panic(|| on_fulfilled(value)),
+                Settled::Rejected(reason) => catch_panic(|| on_rejected(reason)),
+                Settled::Cancelled => return promise_settled.cancel(),
             };
-            let reject = move |reason| {
-                let mut prev_reason: Option<String> = reason;
-                for handler in handlers_reject.lock().unwrap().take().unwrap().into_iter() {
-                    let handler: Handler = handler;
-                    if handler.resolve == false {
-                        prev_reason = (handler.handler)(prev_reason.clone());
-                    }
+            promise_settled.settle(result.unwrap_or_else(|panicked| Err(F::from(panicked))))
+        });
+        promise
+    }
+
+    pub fn catch<F, F2>(&self, on_rejected: F2) -> Promise<T, F>
+    where
+        F: Clone + Send + 'static + From<Panicked>,
+        F2: Send + 'static + FnOnce(E) -> Result<T, F>,
+    {
+        self.then(Ok, on_rejected)
+    }
+
+    pub fn map<U, F1>(&self, on_fulfilled: F1) -> Promise<U, E>
+    where
+        U: Clone + Send + 'static,
+        E: From<Panicked>,
+        F1: Send + 'static + FnOnce(T) -> U,
+    {
+        self.then(move |value| Ok(on_fulfilled(value)), Err)
+    }
+
+    pub fn map_err<F, F2>(&self, on_rejected: F2) -> Promise<T, F>
+    where
+        F: Clone + Send + 'static + From<Panicked>,
+        F2: Send + 'static + FnOnce(E) -> F,
+    {
+        self.then(Ok, move |reason| Err(on_rejected(reason)))
+    }
+
+    pub fn and_then<U, F1>(&self, on_fulfilled: F1) -> Promise<U, E>
+    where
+        U: Clone + Send + 'static,
+        E: From<Panicked>,
+        F1: Send + 'static + FnOnce(T) -> Promise<U, E>,
+    {
+        let promise = self.derived();
+        let promise_settled = promise.clone();
+        self.on_settled(move |settled| match settled {
+            Settled::Fulfilled(value) => match catch_panic(|| on_fulfilled(value)) {
+                Ok(next) => promise_settled.adopt(&next),
+                Err(panicked) => promise_settled.settle(Err(E::from(panicked))),
+            },
+            Settled::Rejected(reason) => promise_settled.settle(Err(reason)),
+            Settled::Cancelled => promise_settled.cancel(),
+        });
+        promise
+    }
+
+    pub fn or_else<F, F2>(&self, on_rejected: F2) -> Promise<T, F>
+    where
+        F: Clone + Send + 'static + From<Panicked>,
+        F2: Send + 'static + FnOnce(E) -> Promise<T, F>,
+    {
+        let promise = self.derived();
+        let promise_settled = promise.clone();
+        self.on_settled(move |settled| match settled {
+            Settled::Fulfilled(value) => promise_settled.settle(Ok(value)),
+            Settled::Rejected(reason) => match catch_panic(|| on_rejected(reason)) {
+                Ok(next) => promise_settled.adopt(&next),
+                Err(panicked) => promise_settled.settle(Err(F::from(panicked))),
+            },
+            Settled::Cancelled => promise_settled.cancel(),
+        });
+        promise
+    }
+
+    pub fn finally<F1>(&self, on_settled: F1) -> Promise<T, E>
+    where
+        E: From<Panicked>,
+        F1: Send + 'static + FnOnce() -> Result<(), E>,
+    {
+        let promise = self.derived();
+        let promise_settled = promise.clone();
+        self.on_settled(move |settled| {
+            let cleanup = catch_panic(on_settled).unwrap_or_else(|panicked| Err(E::from(panicked)));
+            match (cleanup, settled) {
+                (Err(reason), _) => promise_settled.settle(Err(reason)),
+                (Ok(()), Settled::Cancelled) => promise_settled.cancel(),
+                (Ok(()), settled) => {
+                    promise_settled.try_settle(settled);
                 }
-                let mut result_reject = result_reject.lock().unwrap();
-                *result_reject = prev_reason;
-                let mut state_guard = status_reject.lock().unwrap();
-                let state = state_guard.as_mut().unwrap();
-                *state = Status::Rejected;
-            };
+            }
+        });
+        promise
+    }
 
-            executor(&resolve, &reject);
+    pub fn after<F1>(duration: Duration, f: F1) -> Promise<T, E>
+    where
+        E: From<Panicked>,
+        F1: Send + 'static + FnOnce() -> T,
+    {
+        Promise::<(), E>::delay(duration).map(move |()| f())
+    }
+
+    pub fn retry<F1>(factory: F1, policy: RetryPolicy<E>) -> Promise<T, E>
+    where
+        E: From<Panicked> + From<TimedOut>,
+        F1: Fn() -> Promise<T, E> + Send + Sync + 'static,
+    {
+        retry::start(factory, policy)
+    }
+
+    pub fn timeout(&self, timeout: Duration) -> Promise<T, E>
+    where
+        E: From<TimedOut>,
+    {
+        self.deadline(Instant::now() + timeout)
+    }
+
+    pub fn deadline(&self, deadline: Instant) -> Promise<T, E>
+    where
+        E: From<TimedOut>,
+    {
+        let promise = self.derived();
+        let promise_expired = promise.clone();
+        let id = timer().schedule(deadline, move || {
+            promise_expired.settle_detached(Err(E::from(TimedOut)));
+        });
+        promise.core.token.on_cancel(move || timer().cancel(id));
+        let promise_settled = promise.clone();
+        self.on_settled(move |settled| {
+            timer().cancel(id);
+            match settled {
+                Settled::Cancelled => promise_settled.cancel(),
+                settled => {
+                    promise_settled.try_settle(settled);
+                }
+            }
         });
+        promise
+    }
 
-        Promise {
-            handlers,
-            status,
-            value: result,
-            thread,
+    pub fn a_await(&self) -> Result<T, E>
+    where
+        E: From<Cancelled>,
+    {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut key = None;
+        loop {
+            if let Poll::Ready(settled) = self.poll_settled(&mut key, &mut cx) {
+                return settled.into_result();
+            }
+            handlers::run_queued();
+            thread::park();
         }
     }
 
-    pub fn then<F1, F2>(&mut self, on_fulfilled: F1, on_rejected: F2) -> &mut Promise
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<Result<T, E>, TimedOut>
     where
-        F1: Send + 'static + Fn(Option<String>) -> Option<String>,
-        F2: Send + 'static + Fn(Option<String>) -> Option<String>,
+        E: From<Cancelled>,
     {
-        let status = self.status.lock().unwrap().clone().unwrap();
-        match status {
-            Status::Fulfilled => {
-                let result_resolve = self.value.clone();
-                let mut value = result_resolve.lock().unwrap();
-                let prev_value = value.clone();
-                *value = (on_fulfilled)(prev_value);
+        let deadline = Instant::now() + timeout;
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut key = None;
+        loop {
+            if let Poll::Ready(settled) = self.poll_settled(&mut key, &mut cx) {
+                return Ok(settled.into_result());
             }
-            Status::Rejected => {
-                let result_reject = self.value.clone();
-                let mut reason = result_reject.lock().unwrap();
-                let prev_reason = reason.clone();
-                *reason = (on_rejected)(prev_reason);
+            let now = Instant::now();
+            if now >= deadline {
+                self.core.forget_waker(key);
+                return Err(TimedOut);
             }
-            Status::Pending => {
-                let handler_fulfilled = Handler {
-                    resolve: true,
-                    handler: Box::new(on_fulfilled),
-                };
-                let handler_rejected = Handler {
-                    resolve: false,
-                    handler: Box::new(on_rejected),
-                };
-                self.handlers
-                    .lock()
-                    .unwrap()
-                    .as_mut()
-                    .unwrap()
-                    .push(handler_fulfilled);
-                self.handlers
-                    .lock()
-                    .unwrap()
-                    .as_mut()
-                    .unwrap()
-                    .push(handler_rejected);
-            }
-        }
-        self
-    }
-
-    pub fn catch<F>(&mut self, on_rejected: F) -> &mut Promise
-    where
-        F: Send + 'static + Fn(Option<String>) -> Option<String>,
-    {
-        let status = self.status.lock().unwrap().clone().unwrap();
-        match status {
-            Status::Fulfilled => {}
-            Status::Rejected => {
-                let result_reject = self.value.clone();
-                let mut reason = result_reject.lock().unwrap();
-                let prev_reason = reason.clone();
-                *reason = (on_rejected)(prev_reason);
-            }
-            Status::Pending => {
-                let handler = Handler {
-                    resolve: false,
-                    handler: Box::new(on_rejected),
-                };
-                self.handlers
-                    .lock()
-                    .unwrap()
-                    .as_mut()
-                    .unwrap()
-                    .push(handler);
+            handlers::run_queued();
+            thread::park_timeout(deadline - now);
+        }
+    }
+
+    pub fn resolve(value: T) -> Promise<T, E> {
+        let promise = Promise::pending();
+        promise.settle(Ok(value));
+        promise
+    }
+
+    pub fn reject(reason: E) -> Promise<T, E> {
+        let promise = Promise::pending();
+        promise.settle(Err(reason));
+        promise
+    }
+
+    pub fn race(promises: Vec<Promise<T, E>>) -> Promise<T, E> {
+        let promise = Promise::pending();
+        promise.cancel_inputs_on_cancel(&promises);
+        for input in promises.iter() {
+            promise.adopt(input);
+        }
+        promise
+    }
+
+    // Like `race`, but cancels the inputs that are still pending once the
+    // first one settles.
+    pub fn race_cancelling(promises: Vec<Promise<T, E>>) -> Promise<T, E> {
+        let promise = Promise::race(promises.clone());
+        promise.on_settled(move |_| {
+            for input in promises.iter() {
+                if input.is_pending() {
+                    input.cancel();
+                }
             }
+        });
+        promise
+    }
+
+    pub fn any(promises: Vec<Promise<T, E>>) -> Promise<T, AggregateError<E>> {
+        let promise = Promise::pending();
+        if promises.is_empty() {
+            promise.settle(Err(AggregateError {
+                errors: Vec::new(),
+                cancelled: false,
+            }));
+            return promise;
+        }
+        promise.cancel_inputs_on_cancel(&promises);
+        // A cancelled input leaves no reason to report, so if nothing fulfils
+        // the aggregate is cancelled rather than rejected with a gap.
+        let remaining = Arc::new(Mutex::new((promises.len(), vec![None; promises.len()])));
+        for (index, input) in promises.iter().enumerate() {
+            let promise = promise.clone();
+            let remaining = remaining.clone();
+            input.on_settled(move |settled| {
+                let reason = match settled {
+                    Settled::Fulfilled(value) => return promise.settle(Ok(value)),
+                    Settled::Rejected(reason) => Some(reason),
+                    Settled::Cancelled => None,
+                };
+                let mut remaining = remaining.lock().unwrap();
+                let (count, errors) = &mut *remaining;
+                errors[index] = Some(reason);
+                *count -= 1;
+                if *count == 0 {
+                    let errors: Option<Vec<E>> = errors.drain(..).flatten().collect();
+                    drop(remaining);
+                    match errors {
+                        Some(errors) => promise.settle(Err(AggregateError {
+                            errors,
+                            cancelled: false,
+                        })),
+                        None => {
+                            promise.try_settle(Settled::Cancelled);
+                        }
+                    }
+                }
+            });
         }
-        self
+        promise
     }
 
-    pub fn a_await(self) {
-        let _ = self.thread.join();
+    pub fn all_settled(promises: Vec<Promise<T, E>>) -> Promise<Vec<Settled<T, E>>, Cancelled> {
+        let promise = Promise::pending();
+        if promises.is_empty() {
+            promise.settle(Ok(Vec::new()));
+            return promise;
+        }
+        promise.cancel_inputs_on_cancel(&promises);
+        let remaining = Arc::new(Mutex::new((promises.len(), vec![None; promises.len()])));
+        for (index, input) in promises.iter().enumerate() {
+            let promise = promise.clone();
+            let remaining = remaining.clone();
+            input.on_settled(move |settled| {
+                let mut remaining = remaining.lock().unwrap();
+                let (count, outcomes) = &mut *remaining;
+                outcomes[index] = Some(settled);
+                *count -= 1;
+                if *count == 0 {
+                    let outcomes = outcomes.drain(..).flatten().collect();
+                    drop(remaining);
+                    promise.settle(Ok(outcomes));
+                }
+            });
+        }
+        promise
     }
 
-    pub fn resolve(value: Option<String>) -> Promise {
-        Promise::new(move |resolve, _| {
-            resolve(value.clone());
-        })
+    // Runs at most `limit` promises from `f` at a time; panics if `limit` is
+    // zero.
+    pub fn map_limit<I, F1>(items: I, limit: usize, f: F1) -> Promise<Vec<T>, E>
+    where
+        E: From<Panicked>,
+        I: IntoIterator,
+        I::IntoIter: Send + 'static,
+        F1: Fn(I::Item) -> Promise<T, E> + Send + Sync + 'static,
+    {
+        limit::start(items.into_iter(), limit, f)
     }
 
-    pub fn reject(reason: Option<String>) -> Promise {
-        Promise::new(move |_, reject| {
-            reject(reason.clone());
-        })
+    pub fn all(promises: Vec<Promise<T, E>>) -> Promise<Vec<T>, E> {
+        let promise = Promise::pending();
+        if promises.is_empty() {
+            promise.settle(Ok(Vec::new()));
+            return promise;
+        }
+        promise.cancel_inputs_on_cancel(&promises);
+        let remaining = Arc::new(Mutex::new((promises.len(), vec![None; promises.len()])));
+        for (index, input) in promises.iter().enumerate() {
+            let promise = promise.clone();
+            let remaining = remaining.clone();
+            input.on_settled(move |settled| match settled {
+                Settled::Fulfilled(value) => {
+                    let mut remaining = remaining.lock().unwrap();
+                    let (count, values) = &mut *remaining;
+                    values[index] = Some(value);
+                    *count -= 1;
+                    if *count == 0 {
+                        let values = values.drain(..).flatten().collect();
+                        drop(remaining);
+                        promise.settle(Ok(values));
+                    }
+                }
+                Settled::Rejected(reason) => promise.settle(Err(reason)),
+                Settled::Cancelled => promise.cancel(),
+            });
+        }
+        promise
     }
 
-    pub fn all(promises: Vec<Promise>) -> Promise {
-        Promise::all_ex(promises, ";")
-    }
-
-    pub fn all_ex(promises: Vec<Promise>, delimeter: &str) -> Promise {
-        let mut rejected = false;
-        let mut resolved_result: Vec<String> = vec![];
-        let mut first_reject_reason = String::new();
-        for promise in promises.into_iter() {
-            let _ = promise.thread.join();
-            let status = promise.status.lock().unwrap().clone().unwrap();
-            let value = promise
-                .value
-                .lock()
-                .unwrap()
-                .clone()
-                .unwrap_or(String::new());
-            match status {
-                Status::Fulfilled => {
-                    resolved_result.push(value);
+    pub fn zip<U>(&self, other: &Promise<U, E>) -> Promise<(T, U), E>
+    where
+        U: Clone + Send + 'static,
+    {
+        let promise = Promise::pending();
+        promise.cancel_inputs_on_cancel(std::slice::from_ref(self));
+        promise.cancel_inputs_on_cancel(std::slice::from_ref(other));
+        let slots = Arc::new(Mutex::new((None, None)));
+        {
+            let promise = promise.clone();
+            let slots = slots.clone();
+            self.on_settled(move |settled| match settled {
+                Settled::Fulfilled(value) => {
+                    let mut slots = slots.lock().unwrap();
+                    match std::mem::take(&mut *slots) {
+                        (_, Some(other)) => promise.settle(Ok((value, other))),
+                        (_, None) => *slots = (Some(value), None),
+                    }
                 }
-                Status::Rejected => {
-                    rejected = true;
-                    first_reject_reason = value;
+                Settled::Rejected(reason) => promise.settle(Err(reason)),
+                Settled::Cancelled => promise.cancel(),
+            });
+        }
+        {
+            let promise = promise.clone();
+            other.on_settled(move |settled| match settled {
+                Settled::Fulfilled(other) => {
+                    let mut slots = slots.lock().unwrap();
+                    match std::mem::take(&mut *slots) {
+                        (Some(value), _) => promise.settle(Ok((value, other))),
+                        (None, _) => *slots = (None, Some(other)),
+                    }
                 }
-                Status::Pending => {}
+                Settled::Rejected(reason) => promise.settle(Err(reason)),
+                Settled::Cancelled => promise.cancel(),
+            });
+        }
+        promise
+    }
+
+    // Like `map`, but for internal reshaping that cannot panic, so the
+    // rejection type needs no `From<Panicked>`.
+    fn map_settled<U>(&self, f: fn(T) -> U) -> Promise<U, E>
+    where
+        U: Clone + Send + 'static,
+    {
+        let promise = self.derived();
+        let promise_settled = promise.clone();
+        self.on_settled(move |settled| match settled {
+            Settled::Fulfilled(value) => promise_settled.settle(Ok(f(value))),
+            Settled::Rejected(reason) => promise_settled.settle(Err(reason)),
+            Settled::Cancelled => promise_settled.cancel(),
+        });
+        promise
+    }
+}
+
+pub fn join2<A, B, E>(a: &Promise<A, E>, b: &Promise<B, E>) -> Promise<(A, B), E>
+where
+    A: Clone + Send + 'static,
+    B: Clone + Send + 'static,
+    E: Clone + Send + 'static,
+{
+    a.zip(b)
+}
+
+pub fn join3<A, B, C, E>(
+    a: &Promise<A, E>,
+    b: &Promise<B, E>,
+    c: &Promise<C, E>,
+) -> Promise<(A, B, C), E>
+where
+    A: Clone + Send + 'static,
+    B: Clone + Send + 'static,
+    C: Clone + Send + 'static,
+    E: Clone + Send + 'static,
+{
+    a.zip(b).zip(c).map_settled(|((a, b), c)| (a, b, c))
+}
+
+pub fn join4<A, B, C, D, E>(
+    a: &Promise<A, E>,
+    b: &Promise<B, E>,
+    c: &Promise<C, E>,
+    d: &Promise<D, E>,
+) -> Promise<(A, B, C, D), E>
+where
+    A: Clone + Send + 'static,
+    B: Clone + Send + 'static,
+    C: Clone + Send + 'static,
+    D: Clone + Send + 'static,
+    E: Clone + Send + 'static,
+{
+    a.zip(b)
+        .zip(&c.zip(d))
+        .map_settled(|((a, b), (c, d))| (a, b, c, d))
+}
+
+pub struct Resolver<T: 'static, E: 'static> {
+    promise: Promise<T, E>,
+}
+
+pub struct Rejecter<T: 'static, E: 'static> {
+    promise: Promise<T, E>,
+}
+
+impl<T, E> Clone for Resolver<T, E> {
+    fn clone(&self) -> Self {
+        Resolver {
+            promise: self.promise.clone(),
+        }
+    }
+}
+
+impl<T, E> Clone for Rejecter<T, E> {
+    fn clone(&self) -> Self {
+        Rejecter {
+            promise: self.promise.clone(),
+        }
+    }
+}
+
+impl<T, E> Resolver<T, E>
+where
+    T: Clone + Send + 'static,
+    E: Clone + Send + 'static,
+{
+    pub fn resolve(&self, value: T) {
+        if !self.promise.try_settle(Settled::Fulfilled(value)) {
+            report_ignored_settlement(Status::Fulfilled);
+        }
+    }
+
+    pub fn resolve_with(&self, promise: &Promise<T, E>) {
+        self.promise.adopt(promise);
+    }
+}
+
+impl<T, E> Rejecter<T, E>
+where
+    T: Clone + Send + 'static,
+    E: Clone + Send + 'static,
+{
+    pub fn reject(&self, reason: E) {
+        if !self.promise.try_settle(Settled::Rejected(reason)) {
+            report_ignored_settlement(Status::Rejected);
+        }
+    }
+}
+
+impl<E> Promise<(), E>
+where
+    E: Clone + Send + 'static,
+{
+    pub fn delay(duration: Duration) -> Promise<(), E> {
+        Promise::delay_until(Instant::now() + duration)
+    }
+
+    pub fn delay_until(deadline: Instant) -> Promise<(), E> {
+        fire_at(deadline, CancellationToken::new(), ())
+    }
+}
+
+impl<T, E> Promise<Promise<T, E>, E>
+where
+    T: Clone + Send + 'static,
+    E: Clone + Send + 'static + From<Panicked>,
+{
+    pub fn flatten(&self) -> Promise<T, E> {
+        self.and_then(|promise| promise)
+    }
+}
+
+impl<T, E> Future for Promise<T, E>
+where
+    T: Clone + Send + 'static,
+    E: Clone + Send + 'static + From<Cancelled>,
+{
+    type Output = Result<T, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let promise = self.get_mut();
+        let mut key = promise.waker;
+        let settled = promise.poll_settled(&mut key, cx);
+        promise.waker = key;
+        settled.map(Settled::into_result)
+    }
+}
+
+// A future that is dropped before its promise settles takes its waker along.
+impl<T, E> Drop for Promise<T, E> {
+    fn drop(&mut self) {
+        self.core.forget_waker(self.waker.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Barrier;
+
+    use super::*;
+
+    const ROUNDS: usize = if cfg!(miri) { 10 } else { 10_000 };
+
+    // Resolves on a pool thread as soon as the test thread is ready, so the
+    // settlement races whatever the test does next.
+    fn racing(value: usize) -> (Promise<usize, String>, Arc<Barrier>) {
+        let barrier = Arc::new(Barrier::new(2));
+        let start = barrier.clone();
+        let promise = Promise::new(move |resolve, _| {
+            start.wait();
+            resolve(value);
+        });
+        (promise, barrier)
+    }
+
+    #[test]
+    fn handlers_registered_during_settlement_see_the_value() {
+        for round in 0..ROUNDS {
+            let (promise, barrier) = racing(round);
+            barrier.wait();
+            let derived = promise.then(|value| Ok(value + 1), Err);
+            assert_eq!(
+                derived.wait_timeout(Duration::from_secs(5)),
+                Ok(Ok(round + 1))
+            );
+        }
+    }
+
+    #[test]
+    fn waiters_racing_settlement_are_woken() {
+        for round in 0..ROUNDS {
+            let (promise, barrier) = racing(round);
+            barrier.wait();
+            assert_eq!(promise.wait_timeout(Duration::from_secs(5)), Ok(Ok(round)));
+        }
+    }
+
+    #[test]
+    fn handlers_racing_settlement_run_exactly_once() {
+        const THREADS: usize = 4;
+        const HANDLERS: usize = if cfg!(miri) { 5 } else { 100 };
+        for _ in 0..if cfg!(miri) { 3 } else { 200 } {
+            let (promise, resolver, rejecter) = Promise::<usize, String>::with_resolvers();
+            let calls = Arc::new(AtomicUsize::new(0));
+            let barrier = Arc::new(Barrier::new(THREADS + 2));
+            let mut threads: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    let (promise, calls, barrier) =
+                        (promise.clone(), calls.clone(), barrier.clone());
+                    thread::spawn(move || {
+                        barrier.wait();
+                        for _ in 0..HANDLERS {
+                            let calls = calls.clone();
+                            promise.on_settled(move |_| {
+                                calls.fetch_add(1, Ordering::SeqCst);
+                            });
+                        }
+                    })
+                })
+                .collect();
+            let resolve_barrier = barrier.clone();
+            threads.push(thread::spawn(move || {
+                resolve_barrier.wait();
+                resolver.resolve(1);
+            }));
+            threads.push(thread::spawn(move || {
+                barrier.wait();
+                rejecter.reject("lost".to_string());
+            }));
+            threads
+                .into_iter()
+                .for_each(|thread| thread.join().unwrap());
+            assert_eq!(calls.load(Ordering::SeqCst), THREADS * HANDLERS);
+            let expected = match promise.poll_status() {
+                Status::Fulfilled => Settled::Fulfilled(1),
+                Status::Rejected => Settled::Rejected("lost".to_string()),
+                status => panic!("unexpected status {status:?}"),
+            };
+            assert_eq!(promise.settled(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn derived_promises_settle_once_under_racing_registration() {
+        let (promise, resolver, _) = Promise::<usize, String>::with_resolvers();
+        let registrars: Vec<_> = (0..4)
+            .map(|_| {
+                let promise = promise.clone();
+                thread::spawn(move || {
+                    (0..500)
+                        .map(|_| promise.then(|value| Ok(value * 2), Err))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        resolver.resolve(21);
+        for registrar in registrars {
+            for derived in registrar.join().unwrap() {
+                assert_eq!(derived.try_get(), Some(Ok(42)));
             }
         }
-        if rejected {
-            return Promise::reject(Some(first_reject_reason));
-        } else {
-            return Promise::resolve(Some(resolved_result.join(delimeter)));
+    }
+
+    #[test]
+    fn long_chain_settles_without_recursing() {
+        let (source, resolver, _) = Promise::<u64, String>::with_resolvers();
+        let mut chain = source.clone();
+        for _ in 0..200_000 {
+            chain = chain.then(|value| Ok(value + 1), Err);
+        }
+        resolver.resolve(0);
+        assert_eq!(chain.try_get(), Some(Ok(200_000)));
+    }
+
+    #[test]
+    fn long_chain_settles_on_a_pool_worker() {
+        let source = Promise::<u64, String>::new(|resolve, _| {
+            thread::sleep(Duration::from_millis(20));
+            resolve(0);
+        });
+        let mut chain = source.clone();
+        for _ in 0..3_000 {
+            chain = chain.then(|value| Ok(value + 1), Err);
+        }
+        assert_eq!(chain.a_await(), Ok(3_000));
+    }
+
+    #[test]
+    fn long_pending_chain_drops_without_recursing() {
+        let (source, _, _) = Promise::<u64, String>::with_resolvers();
+        let mut chain = source.clone();
+        for _ in 0..200_000 {
+            chain = chain.then(|value| Ok(value + 1), Err);
+        }
+        drop(chain);
+        drop(source);
+    }
+
+    // Runs `f` from a handler nested `depth` links down a chain.
+    fn inside_chain<R: Clone + Send + 'static>(
+        depth: usize,
+        f: impl FnOnce() -> R + Send + 'static,
+    ) -> Promise<R, String> {
+        let (source, resolver, _) = Promise::<u64, String>::with_resolvers();
+        let mut chain = source;
+        for _ in 0..depth {
+            chain = chain.then(|value| Ok(value + 1), Err);
+        }
+        let result = chain.map(move |_| f());
+        resolver.resolve(0);
+        result
+    }
+
+    #[test]
+    fn handlers_registered_inside_a_handler_run_in_order() {
+        for depth in [0, 500] {
+            let order = inside_chain(depth, || {
+                let order = Arc::new(Mutex::new(Vec::new()));
+                let (promise, resolver, _) = Promise::<u8, String>::with_resolvers();
+                let first = order.clone();
+                promise.map(move |_| first.lock().unwrap().push("first"));
+                resolver.resolve(1);
+                let second = order.clone();
+                promise.map(move |_| second.lock().unwrap().push("second"));
+                order
+            });
+            let order = order.a_await().unwrap();
+            assert_eq!(*order.lock().unwrap(), vec!["first", "second"]);
+        }
+    }
+
+    #[test]
+    fn waiting_inside_a_handler_on_a_derived_promise_completes() {
+        for depth in [0, 500] {
+            let waited = inside_chain(depth, || {
+                let (promise, resolver, _) = Promise::<u8, String>::with_resolvers();
+                let derived = promise.map(|value| value + 1);
+                resolver.resolve(1);
+                derived.wait_timeout(Duration::from_secs(5))
+            });
+            assert_eq!(waited.a_await(), Ok(Ok(Ok(2))));
+        }
+    }
+
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn future_wakes_its_task_on_settlement() {
+        let (promise, resolver, _) = Promise::<u8, String>::with_resolvers();
+        let counter = Arc::new(CountingWaker(AtomicUsize::new(0)));
+        let waker = Waker::from(counter.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = promise.clone();
+        assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+        resolver.resolve(5);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert_eq!(Pin::new(&mut future).poll(&mut cx), Poll::Ready(Ok(5)));
+    }
+
+    fn waiting(promise: &Promise<(), String>) -> usize {
+        promise.core.slot.lock().unwrap().wakers.len()
+    }
+
+    #[test]
+    fn wait_timeout_reports_the_outcome_or_times_out() {
+        let (promise, resolver, _) = Promise::<u8, String>::with_resolvers();
+        assert_eq!(
+            promise.wait_timeout(Duration::from_millis(5)),
+            Err(TimedOut)
+        );
+        resolver.resolve(1);
+        assert_eq!(promise.wait_timeout(Duration::ZERO), Ok(Ok(1)));
+        assert_eq!(promise.a_await(), Ok(1));
+    }
+
+    #[test]
+    fn timed_out_waits_leave_no_wakers_behind() {
+        let (promise, _, _) = Promise::<(), String>::with_resolvers();
+        for _ in 0..20_000 {
+            assert_eq!(promise.wait_timeout(Duration::ZERO), Err(TimedOut));
+        }
+        assert_eq!(waiting(&promise), 0);
+    }
+
+    #[test]
+    fn futures_keep_one_waker_and_drop_it() {
+        let (promise, _, _) = Promise::<(), String>::with_resolvers();
+        let mut future = promise.clone();
+        let mut cx = Context::from_waker(Waker::noop());
+        for _ in 0..10 {
+            assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
         }
+        assert_eq!(waiting(&promise), 1);
+        drop(future);
+        assert_eq!(waiting(&promise), 0);
+    }
+
+    thread_local! {
+        static IGNORED: RefCell<Vec<Status>> = const { RefCell::new(Vec::new()) };
+    }
+
+    #[test]
+    fn later_settlements_are_ignored_and_reported() {
+        set_ignored_settlement_hook(|status| {
+            IGNORED.with(|ignored| ignored.borrow_mut().push(status))
+        });
+        let (promise, resolver, rejecter) = Promise::<u8, String>::with_resolvers();
+        resolver.resolve(1);
+        resolver.resolve(2);
+        rejecter.reject("late".to_string());
+        assert_eq!(promise.try_get(), Some(Ok(1)));
+        let ignored = IGNORED.with(|ignored| ignored.borrow().clone());
+        assert_eq!(ignored, vec![Status::Fulfilled, Status::Rejected]);
+    }
+
+    #[test]
+    fn executor_panics_become_rejections() {
+        let promise = Promise::<u8, String>::new(|_, _| panic!("executor failed"));
+        assert_eq!(
+            promise.a_await(),
+            Err("promise panicked: executor failed".to_string())
+        );
+    }
+
+    #[test]
+    fn handler_panics_reject_the_derived_promise() {
+        let source = Promise::<u8, String>::resolve(1);
+        let derived = source.map(|_| -> u8 { panic!("handler failed") });
+        assert_eq!(
+            derived.a_await(),
+            Err("promise panicked: handler failed".to_string())
+        );
+        assert_eq!(source.try_get(), Some(Ok(1)));
+    }
+
+    #[test]
+    fn handler_results_settle_the_derived_promise() {
+        let source = Promise::<u8, String>::resolve(1);
+        let rejected = source.then(|value| Err::<u8, _>(format!("refused {value}")), Err);
+        assert_eq!(rejected.a_await(), Err("refused 1".to_string()));
+        let recovered = rejected.catch(|reason| Ok::<_, String>(reason.len() as u8));
+        assert_eq!(recovered.a_await(), Ok(9));
+        let wrapped = Promise::<u8, String>::reject("bad".to_string())
+            .map_err(|reason| format!("wrapped {reason}"));
+        assert_eq!(wrapped.a_await(), Err("wrapped bad".to_string()));
+    }
+
+    #[test]
+    fn branches_get_their_own_results() {
+        let source = Promise::<u8, String>::resolve(10);
+        let doubled = source.map(|value| value * 2);
+        let halved = source.map(|value| value / 2);
+        let described = source.map(|value| format!("got {value}"));
+        assert_eq!(doubled.a_await(), Ok(20));
+        assert_eq!(halved.a_await(), Ok(5));
+        assert_eq!(described.a_await(), Ok("got 10".to_string()));
+        assert_eq!(source.a_await(), Ok(10));
+    }
+
+    #[test]
+    fn executor_can_resolve_with_a_promise() {
+        let (inner, inner_resolver, _) = Promise::<u8, String>::with_resolvers();
+        let outer = Promise::new_cancellable(move |resolver, _, _| resolver.resolve_with(&inner));
+        thread::sleep(Duration::from_millis(10));
+        assert!(outer.is_pending());
+        inner_resolver.resolve(7);
+        assert_eq!(outer.a_await(), Ok(7));
+    }
+
+    #[test]
+    fn handlers_returning_promises_are_adopted() {
+        let (inner, inner_resolver, _) = Promise::<u8, String>::with_resolvers();
+        let chained = Promise::<u8, String>::resolve(1).and_then(move |_| inner);
+        assert!(chained.is_pending());
+        inner_resolver.resolve(2);
+        assert_eq!(chained.a_await(), Ok(2));
+
+        let recovered = Promise::<u8, String>::reject("first".to_string())
+            .or_else(|reason| Promise::<u8, String>::resolve(reason.len() as u8));
+        assert_eq!(recovered.a_await(), Ok(5));
+
+        let nested = Promise::<Promise<u8, String>, String>::resolve(Promise::resolve(3));
+        assert_eq!(nested.flatten().a_await(), Ok(3));
+
+        let (outer, resolver, _) = Promise::<u8, String>::with_resolvers();
+        resolver.resolve_with(&Promise::reject("adopted".to_string()));
+        assert_eq!(outer.a_await(), Err("adopted".to_string()));
+    }
+
+    #[test]
+    fn race_settles_like_the_first_input() {
+        let (slow, _, _) = Promise::<u8, String>::with_resolvers();
+        let (fast, _, rejecter) = Promise::<u8, String>::with_resolvers();
+        let race = Promise::race(vec![slow.clone(), fast]);
+        assert!(race.is_pending());
+        rejecter.reject("fast".to_string());
+        assert_eq!(race.a_await(), Err("fast".to_string()));
+        assert!(slow.is_pending());
+
+        let cancelling = Promise::race_cancelling(vec![slow.clone(), Promise::resolve(1)]);
+        assert_eq!(cancelling.a_await(), Ok(1));
+        assert!(slow.is_cancelled());
+    }
+
+    #[test]
+    fn any_fulfils_with_the_first_fulfilment() {
+        let (late, late_resolver, _) = Promise::<u8, String>::with_resolvers();
+        let (early, _, early_rejecter) = Promise::<u8, String>::with_resolvers();
+        let any = Promise::any(vec![late, early]);
+        early_rejecter.reject("early".to_string());
+        assert!(any.is_pending());
+        late_resolver.resolve(4);
+        assert_eq!(any.a_await(), Ok(4));
+    }
+
+    #[test]
+    fn any_lists_reasons_in_input_order() {
+        let (a, _, reject_a) = Promise::<u8, String>::with_resolvers();
+        let (b, _, reject_b) = Promise::<u8, String>::with_resolvers();
+        let any = Promise::any(vec![a, b]);
+        reject_b.reject("b".to_string());
+        reject_a.reject("a".to_string());
+        let expected = AggregateError {
+            errors: vec!["a".to_string(), "b".to_string()],
+            cancelled: false,
+        };
+        assert_eq!(any.a_await(), Err(expected));
+
+        let empty = Promise::<u8, String>::any(Vec::new());
+        let expected = AggregateError {
+            errors: Vec::new(),
+            cancelled: false,
+        };
+        assert_eq!(empty.try_get(), Some(Err(expected)));
+    }
+
+    #[test]
+    fn all_settled_reports_every_outcome_in_input_order() {
+        let (cancelled, _, _) = Promise::<u8, String>::with_resolvers();
+        let (late, late_resolver, _) = Promise::<u8, String>::with_resolvers();
+        let outcomes = Promise::all_settled(vec![
+            late,
+            Promise::reject("no".to_string()),
+            cancelled.clone(),
+        ]);
+        cancelled.cancel();
+        assert!(outcomes.is_pending());
+        late_resolver.resolve(1);
+        let expected = vec![
+            Settled::Fulfilled(1),
+            Settled::Rejected("no".to_string()),
+            Settled::Cancelled,
+        ];
+        assert_eq!(outcomes.a_await(), Ok(expected));
+        assert_eq!(
+            Promise::<u8, String>::all_settled(Vec::new()).try_get(),
+            Some(Ok(Vec::new()))
+        );
+    }
+
+    #[test]
+    fn all_rejects_on_the_first_rejection_without_waiting() {
+        let (pending, _, _) = Promise::<u8, String>::with_resolvers();
+        let (failing, _, rejecter) = Promise::<u8, String>::with_resolvers();
+        let all = Promise::all(vec![pending.clone(), failing]);
+        rejecter.reject("failed".to_string());
+        assert_eq!(all.try_get(), Some(Err("failed".to_string())));
+        assert!(pending.is_pending());
+    }
+
+    #[test]
+    fn all_and_joins_keep_input_order() {
+        let (first, first_resolver, _) = Promise::<u8, String>::with_resolvers();
+        let all = Promise::all(vec![first, Promise::resolve(2), Promise::resolve(3)]);
+        first_resolver.resolve(1);
+        assert_eq!(all.a_await(), Ok(vec![1, 2, 3]));
+        assert_eq!(
+            Promise::<u8, String>::all(Vec::new()).try_get(),
+            Some(Ok(Vec::new()))
+        );
+
+        let number = Promise::<u8, String>::resolve(1);
+        let text = Promise::<String, String>::resolve("two".to_string());
+        let flag = Promise::<bool, String>::resolve(true);
+        let ratio = Promise::<f32, String>::resolve(0.5);
+        assert_eq!(number.zip(&text).a_await(), Ok((1, "two".to_string())));
+        assert_eq!(join2(&number, &flag).a_await(), Ok((1, true)));
+        assert_eq!(
+            join3(&number, &text, &flag).a_await(),
+            Ok((1, "two".to_string(), true))
+        );
+        assert_eq!(
+            join4(&number, &text, &flag, &ratio).a_await(),
+            Ok((1, "two".to_string(), true, 0.5))
+        );
+        let failed = Promise::<bool, String>::reject("no".to_string());
+        assert_eq!(join2(&number, &failed).a_await(), Err("no".to_string()));
+    }
+
+    #[test]
+    fn finally_runs_once_and_passes_the_outcome_through() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let fulfilled = Promise::<u8, String>::resolve(1).finally(move || {
+            counted.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+        assert_eq!(fulfilled.a_await(), Ok(1));
+        let counted = calls.clone();
+        let rejected = Promise::<u8, String>::reject("no".to_string()).finally(move || {
+            counted.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+        assert_eq!(rejected.a_await(), Err("no".to_string()));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let failed_cleanup =
+            Promise::<u8, String>::resolve(1).finally(|| Err("cleanup failed".to_string()));
+        assert_eq!(failed_cleanup.a_await(), Err("cleanup failed".to_string()));
+        let panicked_cleanup = Promise::<u8, String>::resolve(1).finally(|| panic!("oops"));
+        assert_eq!(
+            panicked_cleanup.a_await(),
+            Err("promise panicked: oops".to_string())
+        );
+    }
+
+    #[test]
+    fn resolvers_settle_from_other_threads() {
+        let (promise, resolver, rejecter) = Promise::<u8, String>::with_resolvers();
+        let resolvers: Vec<_> = (0..4)
+            .map(|value| {
+                let resolver = resolver.clone();
+                thread::spawn(move || resolver.resolve(value))
+            })
+            .collect();
+        resolvers
+            .into_iter()
+            .for_each(|thread| thread.join().unwrap());
+        rejecter.reject("late".to_string());
+        assert!(matches!(promise.a_await(), Ok(0..=3)));
+    }
+
+    #[test]
+    fn any_results_can_be_awaited_and_inspected() {
+        let any = Promise::any(vec![
+            Promise::<u8, String>::reject("a".to_string()),
+            Promise::reject("b".to_string()),
+        ]);
+        let expected = AggregateError {
+            errors: vec!["a".to_string(), "b".to_string()],
+            cancelled: false,
+        };
+        assert_eq!(any.try_get(), Some(Err(expected.clone())));
+        assert_eq!(any.a_await(), Err(expected.clone()));
+        assert_eq!(any.wait_timeout(Duration::ZERO), Ok(Err(expected)));
+
+        let (input, _, _) = Promise::<u8, String>::with_resolvers();
+        let any = Promise::any(vec![input]);
+        any.cancel();
+        assert_eq!(any.a_await(), Err(AggregateError::from(Cancelled)));
+    }
+
+    #[test]
+    fn any_with_a_cancelled_input_and_no_fulfilment_is_cancelled() {
+        let (cancelled, _, _) = Promise::<u8, String>::with_resolvers();
+        let any = Promise::any(vec![
+            Promise::reject("a".to_string()),
+            cancelled.clone(),
+            Promise::reject("c".to_string()),
+        ]);
+        cancelled.cancel();
+        assert!(any.is_cancelled());
+
+        let (cancelled, _, _) = Promise::<u8, String>::with_resolvers();
+        let any = Promise::any(vec![cancelled.clone(), Promise::resolve(2)]);
+        cancelled.cancel();
+        assert_eq!(any.a_await(), Ok(2));
+    }
+
+    struct Deferred(Mutex<Vec<executor::Task>>);
+
+    impl Executor for Deferred {
+        fn execute(&self, task: executor::Task) {
+            self.0.lock().unwrap().push(task);
+        }
+    }
+
+    #[test]
+    fn cancelling_a_source_cancels_its_chain_and_executor() {
+        let deferred = Deferred(Mutex::new(Vec::new()));
+        let ran = Arc::new(AtomicUsize::new(0));
+        let counted = ran.clone();
+        let source = Promise::<u8, String>::new_cancellable_on(&deferred, move |_, _, _| {
+            counted.fetch_add(1, Ordering::SeqCst);
+        });
+        let chained = source.map(|value| value + 1).map(|value| value * 2);
+        source.cancel();
+        deferred.0.lock().unwrap().drain(..).for_each(|task| task());
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+        assert!(source.is_cancelled());
+        assert_eq!(chained.a_await(), Err(Cancelled.to_string()));
+    }
+
+    #[test]
+    fn executors_observe_cancellation_through_their_token() {
+        let (started, started_resolver, _) = Promise::<(), String>::with_resolvers();
+        let promise = Promise::<u8, String>::new_cancellable(move |_, _, token| {
+            started_resolver.resolve(());
+            while !token.is_cancelled() {
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        started.a_await().unwrap();
+        promise.cancel();
+        assert_eq!(promise.a_await(), Err(Cancelled.to_string()));
+    }
+
+    #[test]
+    fn cancelling_all_cancels_its_inputs() {
+        let (a, _, _) = Promise::<u8, String>::with_resolvers();
+        let (b, _, _) = Promise::<u8, String>::with_resolvers();
+        let all = Promise::all(vec![a.clone(), b.clone()]);
+        all.cancel();
+        assert!(a.is_cancelled() && b.is_cancelled());
+
+        let (c, _, _) = Promise::<u8, String>::with_resolvers();
+        let all = Promise::all(vec![c.clone(), Promise::resolve(1)]);
+        c.cancel();
+        assert!(all.is_cancelled());
+    }
+
+    #[test]
+    fn cancelling_all_leaves_settled_inputs_alone() {
+        let a = Promise::<u8, String>::resolve(1);
+        let (b, _, _) = Promise::<u8, String>::with_resolvers();
+        let all = Promise::all(vec![a.clone(), b.clone()]);
+        b.cancel();
+        assert!(all.is_cancelled());
+        assert!(!a.token().is_cancelled());
+        assert_eq!(a.try_get(), Some(Ok(1)));
+        assert_eq!(a.map(|value| value + 1).a_await(), Ok(2));
+    }
+
+    #[test]
+    fn settled_promises_ignore_cancel() {
+        let fulfilled = Promise::<u8, String>::resolve(1);
+        fulfilled.cancel();
+        assert!(fulfilled.is_fulfilled());
+        assert_eq!(fulfilled.map(|value| value + 1).a_await(), Ok(2));
+
+        let rejected = Promise::<u8, String>::reject("no".to_string());
+        rejected.token().cancel();
+        assert_eq!(
+            rejected.map(|value| value + 1).a_await(),
+            Err("no".to_string())
+        );
+    }
+
+    #[test]
+    fn timeout_and_deadline_only_reject_slow_promises() {
+        let (never, _, _) = Promise::<u8, String>::with_resolvers();
+        assert_eq!(
+            never.timeout(Duration::from_millis(10)).a_await(),
+            Err(TimedOut.to_string())
+        );
+        let expired = never.deadline(Instant::now() - Duration::from_secs(1));
+        assert_eq!(expired.a_await(), Err(TimedOut.to_string()));
+        assert!(never.is_pending());
+
+        let quick = Promise::<u8, String>::resolve(1).timeout(Duration::from_secs(60));
+        assert_eq!(quick.a_await(), Ok(1));
+        let cancelled = never.timeout(Duration::from_secs(60));
+        cancelled.cancel();
+        assert_eq!(cancelled.a_await(), Err(Cancelled.to_string()));
+    }
+
+    #[test]
+    fn state_inspection_tracks_each_outcome() {
+        let (promise, resolver, _) = Promise::<u8, String>::with_resolvers();
+        assert!(promise.is_pending());
+        assert_eq!(promise.poll_status(), Status::Pending);
+        assert_eq!(promise.try_get(), None);
+        resolver.resolve(1);
+        assert!(promise.is_fulfilled());
+        assert_eq!(promise.try_get(), Some(Ok(1)));
+
+        let rejected = Promise::<u8, String>::reject("no".to_string());
+        assert!(rejected.is_rejected());
+        assert_eq!(rejected.poll_status(), Status::Rejected);
+        assert_eq!(rejected.try_get(), Some(Err("no".to_string())));
+
+        let (cancelled, _, _) = Promise::<u8, String>::with_resolvers();
+        cancelled.cancel();
+        assert!(cancelled.is_cancelled());
+        assert!(cancelled.token().is_cancelled());
+        assert_eq!(cancelled.try_get(), Some(Err(Cancelled.to_string())));
     }
 }
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::{catch_panic, CancellationToken, Panicked, Promise, Settled};
+
+struct MapLimit<I, F, T: 'static, E: 'static> {
+    f: F,
+    limit: usize,
+    promise: Promise<Vec<T>, E>,
+    state: Mutex<State<I, T>>,
+}
+
+struct State<I, T> {
+    items: I,
+    results: Vec<Option<T>>,
+    running: HashMap<usize, CancellationToken>,
+    exhausted: bool,
+    stopped: bool,
+    pumping: bool,
+    again: bool,
+}
+
+pub(crate) fn start<I, F, T, E>(items: I, limit: usize, f: F) -> Promise<Vec<T>, E>
+where
+    I: Iterator + Send + 'static,
+    F: Fn(I::Item) -> Promise<T, E> + Send + Sync + 'static,
+    T: Clone + Send + 'static,
+    E: Clone + Send + 'static + From<Panicked>,
+{
+    assert!(limit > 0, "map_limit limit must be non-zero");
+    let map = Arc::new(MapLimit {
+        f,
+        limit,
+        promise: Promise::pending(),
+        state: Mutex::new(State {
+            items,
+            results: Vec::new(),
+            running: HashMap::new(),
+            exhausted: false,
+            stopped: false,
+            pumping: false,
+            again: false,
+        }),
+    });
+    let promise = map.promise.clone();
+    let map_cancelled = Arc::downgrade(&map);
+    promise.token().on_cancel(move || {
+        if let Some(map) = map_cancelled.upgrade() {
+            map.stop().values().for_each(CancellationToken::cancel);
+        }
+    });
+    pump(&map);
+    promise
+}
+
+impl<I, F, T, E> MapLimit<I, F, T, E> {
+    fn stop(&self) -> HashMap<usize, CancellationToken> {
+        let mut state = self.state.lock().unwrap();
+        state.stopped = true;
+        std::mem::take(&mut state.running)
+    }
+}
+
+// Starts work until `limit` promises are in flight. Completions that arrive
+// while a pump is already running (possibly further up this very stack) just
+// ask it to go round again, so synchronously settled work cannot recurse.
+fn pump<I, F, T, E>(map: &Arc<MapLimit<I, F, T, E>>)
+where
+    I: Iterator + Send + 'static,
+    F: Fn(I::Item) -> Promise<T, E> + Send + Sync + 'static,
+    T: Clone + Send + 'static,
+    E: Clone + Send + 'static + From<Panicked>,
+{
+    {
+        let mut state = map.state.lock().unwrap();
+        if state.pumping {
+            state.again = true;
+            return;
+        }
+        state.pumping = true;
+    }
+    loop {
+        let mut state = map.state.lock().unwrap();
+        let next = if state.stopped || state.exhausted || state.running.len() >= map.limit {
+            None
+        } else {
+            match state.items.next() {
+                Some(item) => {
+                    state.results.push(None);
+                    Some((state.results.len() - 1, item))
+                }
+                None => {
+                    state.exhausted = true;
+                    None
+                }
+            }
+        };
+        let Some((index, item)) = next else {
+            if state.again {
+                state.again = false;
+                continue;
+            }
+            state.pumping = false;
+            if state.exhausted && state.running.is_empty() && !state.stopped {
+                state.stopped = true;
+                let results = state.results.drain(..).flatten().collect();
+                drop(state);
+                map.promise.settle(Ok(results));
+            }
+            return;
+        };
+        drop(state);
+
+        let current = match catch_panic(|| (map.f)(item)) {
+            Ok(current) => current,
+            Err(panicked) => Promise::reject(E::from(panicked)),
+        };
+        map.state
+            .lock()
+            .unwrap()
+            .running
+            .insert(index, current.token().clone());
+        let map = map.clone();
+        current.on_settled(move |settled| match settled {
+            Settled::Fulfilled(value) => {
+                {
+                    let mut state = map.state.lock().unwrap();
+                    state.running.remove(&index);
+                    state.results[index] = Some(value);
+                }
+                pump(&map);
+            }
+            Settled::Rejected(reason) => {
+                let mut running = map.stop();
+                running.remove(&index);
+                map.promise.settle(Err(reason));
+                running.values().for_each(CancellationToken::cancel);
+            }
+            Settled::Cancelled => {
+                // Work cancelled by `stop` must not cancel the result it was
+                // stopped for.
+                if !map.state.lock().unwrap().stopped {
+                    map.promise.cancel();
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::Promise;
+
+    #[test]
+    fn stays_within_the_limit_and_keeps_input_order() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (counted, highest) = (running.clone(), peak.clone());
+        let squares = Promise::map_limit(0..20u32, 3, move |item| {
+            let (running, peak) = (counted.clone(), highest.clone());
+            Promise::<u32, String>::new(move |resolve, _| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(2));
+                running.fetch_sub(1, Ordering::SeqCst);
+                resolve(item * item);
+            })
+        });
+        assert_eq!(
+            squares.a_await(),
+            Ok((0..20).map(|item| item * item).collect())
+        );
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[test]
+    fn settles_synchronous_work_without_recursing() {
+        let items = Promise::map_limit(0..200_000u32, 4, Promise::<u32, String>::resolve);
+        assert_eq!(items.a_await().map(|items| items.len()), Ok(200_000));
+    }
+
+    #[test]
+    fn first_rejection_cancels_work_in_flight() {
+        let (slow, _, _) = Promise::<u8, String>::with_resolvers();
+        let in_flight = slow.clone();
+        let mapped = Promise::map_limit(0..2, 2, move |item| match item {
+            0 => in_flight.clone(),
+            _ => Promise::reject("failed".to_string()),
+        });
+        assert_eq!(mapped.a_await(), Err("failed".to_string()));
+        assert!(slow.is_cancelled());
+    }
+
+    #[test]
+    #[should_panic(expected = "map_limit limit must be non-zero")]
+    fn rejects_a_zero_limit() {
+        Promise::<u8, String>::map_limit(0..1, 0, Promise::resolve);
+    }
+}
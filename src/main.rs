@@ -1,41 +1,41 @@
 extern crate promise_rs;
-use promise_rs::Promise;
+use promise_rs::StringPromise;
 
 fn main() {
     println!("1. Create Promise");
-    let mut promise = Promise::new(|resolve, reject| {
+    let promise = StringPromise::new(|resolve, reject| {
         std::thread::sleep(std::time::Duration::from_millis(1));
         println!("3. Resolve resute in new thread");
         if true {
-            resolve(Some("resolve result".to_string()));
+            resolve("resolve result".to_string());
         } else {
-            reject(None);
+            reject(String::new());
         }
     });
 
     println!("2. Add then/catch handlers");
-    promise
+    let promise = promise
         .then(
             |value| {
                 println!("4. On fulfilled - {:?}", &value);
-                Some("changed result".to_string())
+                Ok("changed result".to_string())
             },
             |reason| {
                 println!("4. On rejected - {:?}", &reason);
-                reason
+                Err(reason)
             },
         )
         .then(
             |value| {
                 println!("5. On fulfilled - {:?}", &value);
-                value
+                Ok(value)
             },
-            |reason| reason,
+            Err,
         )
         .catch(|reason| {
             println!("5. On catche - {:?}", &reason);
-            None
+            Err(reason)
         });
 
-    promise.a_await();
+    println!("6. Settled - {:?}", promise.a_await());
 }
@@ -0,0 +1,257 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::executor;
+use crate::timer::timer;
+use crate::{catch_panic, CancellationToken, Panicked, Promise, Settled, TimedOut};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Backoff {
+    Fixed(Duration),
+    Exponential { initial: Duration, max: Duration },
+    DecorrelatedJitter { base: Duration, max: Duration },
+}
+
+impl Backoff {
+    // `attempt` is the 1-based number of the attempt that just failed and
+    // `previous` the delay that preceded it.
+    fn delay(&self, attempt: u32, previous: Duration) -> Duration {
+        match *self {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { initial, max } => initial
+                .checked_mul(2u32.saturating_pow(attempt - 1))
+                .map_or(max, |delay| delay.min(max)),
+            Backoff::DecorrelatedJitter { base, max } => {
+                let upper = previous.max(base).saturating_mul(3).min(max);
+                random_between(base.min(upper), upper)
+            }
+        }
+    }
+}
+
+fn random_between(low: Duration, high: Duration) -> Duration {
+    let span = (high - low).as_nanos() as u64;
+    if span == 0 {
+        return low;
+    }
+    let random = RandomState::new().build_hasher().finish();
+    low + Duration::from_nanos(random % (span + 1))
+}
+
+type RetryIf<E> = Arc<dyn Fn(&E) -> bool + Send + Sync>;
+
+pub struct RetryPolicy<E> {
+    max_attempts: u32,
+    backoff: Backoff,
+    attempt_timeout: Option<Duration>,
+    retry_if: Option<RetryIf<E>>,
+}
+
+impl<E> RetryPolicy<E> {
+    pub fn new(max_attempts: u32) -> RetryPolicy<E> {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            backoff: Backoff::Fixed(Duration::ZERO),
+            attempt_timeout: None,
+            retry_if: None,
+        }
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> RetryPolicy<E> {
+        self.backoff = backoff;
+        self
+    }
+
+    pub fn attempt_timeout(mut self, attempt_timeout: Duration) -> RetryPolicy<E> {
+        self.attempt_timeout = Some(attempt_timeout);
+        self
+    }
+
+    pub fn retry_if<F>(mut self, retry_if: F) -> RetryPolicy<E>
+    where
+        F: Fn(&E) -> bool + Send + Sync + 'static,
+    {
+        self.retry_if = Some(Arc::new(retry_if));
+        self
+    }
+
+    fn should_retry(&self, attempt: u32, reason: &E) -> bool {
+        attempt < self.max_attempts
+            && self
+                .retry_if
+                .as_ref()
+                .is_none_or(|retry_if| retry_if(reason))
+    }
+}
+
+struct Retry<T: 'static, E: 'static, F> {
+    factory: F,
+    policy: RetryPolicy<E>,
+    promise: Promise<T, E>,
+    current: Mutex<Option<CancellationToken>>,
+}
+
+pub(crate) fn start<T, E, F>(factory: F, policy: RetryPolicy<E>) -> Promise<T, E>
+where
+    T: Clone + Send + 'static,
+    E: Clone + Send + 'static + From<Panicked> + From<TimedOut>,
+    F: Fn() -> Promise<T, E> + Send + Sync + 'static,
+{
+    let retry = Arc::new(Retry {
+        factory,
+        policy,
+        promise: Promise::pending(),
+        current: Mutex::new(None),
+    });
+    let promise = retry.promise.clone();
+    let retry_cancelled = Arc::downgrade(&retry);
+    promise.token().on_cancel(move || {
+        if let Some(retry) = retry_cancelled.upgrade() {
+            if let Some(current) = retry.current.lock().unwrap().take() {
+                current.cancel();
+            }
+        }
+    });
+    attempt(retry, 1, Duration::ZERO);
+    promise
+}
+
+fn attempt<T, E, F>(retry: Arc<Retry<T, E, F>>, attempt: u32, delay: Duration)
+where
+    T: Clone + Send + 'static,
+    E: Clone + Send + 'static + From<Panicked> + From<TimedOut>,
+    F: Fn() -> Promise<T, E> + Send + Sync + 'static,
+{
+    if retry.promise.token().is_cancelled() {
+        return;
+    }
+    let started = match catch_panic(&retry.factory) {
+        Ok(started) => started,
+        Err(panicked) => Promise::reject(E::from(panicked)),
+    };
+    *retry.current.lock().unwrap() = Some(started.token().clone());
+    let current = match retry.policy.attempt_timeout {
+        Some(attempt_timeout) => started.timeout(attempt_timeout),
+        None => started.clone(),
+    };
+    current.on_settled(move |settled| match settled {
+        Settled::Fulfilled(value) => retry.promise.settle(Ok(value)),
+        Settled::Cancelled => retry.promise.cancel(),
+        Settled::Rejected(reason) => {
+            // Still pending means the attempt timed out; stop it so hung
+            // attempts do not pile up behind the retries.
+            if started.is_pending() {
+                started.cancel();
+            }
+            if !retry.policy.should_retry(attempt, &reason) {
+                return retry.promise.settle(Err(reason));
+            }
+            let next_delay = retry.policy.backoff.delay(attempt, delay);
+            let retry = retry.clone();
+            timer().schedule(Instant::now() + next_delay, move || {
+                executor::spawn(Box::new(move || {
+                    self::attempt(retry, attempt + 1, next_delay)
+                }))
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Cancelled;
+
+    #[test]
+    fn timed_out_attempts_are_cancelled() {
+        let attempts = Arc::new(Mutex::new(Vec::new()));
+        let started = attempts.clone();
+        let policy = RetryPolicy::new(3).attempt_timeout(Duration::from_millis(10));
+        let retried = Promise::<u8, String>::retry(
+            move || {
+                let (attempt, _, _) = Promise::with_resolvers();
+                started.lock().unwrap().push(attempt.clone());
+                attempt
+            },
+            policy,
+        );
+        assert_eq!(retried.a_await(), Err(TimedOut.to_string()));
+        let attempts = attempts.lock().unwrap();
+        assert_eq!(attempts.len(), 3);
+        assert!(attempts.iter().all(Promise::is_cancelled));
+    }
+
+    #[test]
+    fn retries_until_an_attempt_fulfils() {
+        let attempts = Arc::new(Mutex::new(0));
+        let counted = attempts.clone();
+        let retried = Promise::<u32, String>::retry(
+            move || {
+                let mut attempts = counted.lock().unwrap();
+                *attempts += 1;
+                match *attempts {
+                    3 => Promise::resolve(*attempts),
+                    _ => Promise::reject("flaky".to_string()),
+                }
+            },
+            RetryPolicy::new(5).backoff(Backoff::Fixed(Duration::from_millis(1))),
+        );
+        assert_eq!(retried.a_await(), Ok(3));
+        assert_eq!(*attempts.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn stops_when_retry_if_refuses_or_attempts_run_out() {
+        let attempts = Arc::new(Mutex::new(0));
+        let counted = attempts.clone();
+        let policy = RetryPolicy::new(5).retry_if(|reason: &String| reason != "fatal");
+        let retried = Promise::<u32, String>::retry(
+            move || {
+                *counted.lock().unwrap() += 1;
+                Promise::reject("fatal".to_string())
+            },
+            policy,
+        );
+        assert_eq!(retried.a_await(), Err("fatal".to_string()));
+        assert_eq!(*attempts.lock().unwrap(), 1);
+
+        let retried = Promise::<u32, String>::retry(
+            || Promise::reject("flaky".to_string()),
+            RetryPolicy::new(2),
+        );
+        assert_eq!(retried.a_await(), Err("flaky".to_string()));
+    }
+
+    #[test]
+    fn cancelling_stops_the_current_attempt() {
+        let (attempt, _, _) = Promise::<u32, String>::with_resolvers();
+        let running = attempt.clone();
+        let retried = Promise::retry(move || running.clone(), RetryPolicy::new(3));
+        retried.cancel();
+        assert!(attempt.is_cancelled());
+        assert_eq!(retried.a_await(), Err(Cancelled.to_string()));
+    }
+
+    #[test]
+    fn backoff_delays_grow_and_stay_capped() {
+        let exponential = Backoff::Exponential {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+        };
+        let delays: Vec<_> = (1..=4)
+            .map(|attempt| exponential.delay(attempt, Duration::ZERO))
+            .collect();
+        let expected = [10, 20, 40, 50].map(Duration::from_millis);
+        assert_eq!(delays, expected);
+
+        let (base, max) = (Duration::from_millis(10), Duration::from_millis(100));
+        let jitter = Backoff::DecorrelatedJitter { base, max };
+        let mut previous = Duration::ZERO;
+        for attempt in 1..20 {
+            previous = jitter.delay(attempt, previous);
+            assert!(base <= previous && previous <= max);
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use crate::{Promise, Settled};
+
+// Yields the outcomes of the pushed promises in the order they settle.
+pub struct PromiseSet<T, E> {
+    shared: Arc<Shared<T, E>>,
+    waker: Option<u64>,
+}
+
+struct Shared<T, E> {
+    state: Mutex<State<T, E>>,
+    condvar: Condvar,
+}
+
+// Every waiting future keeps its own waker under its key, and all of them are
+// woken for each outcome, so concurrent waiters cannot shadow one another.
+struct State<T, E> {
+    ready: VecDeque<Settled<T, E>>,
+    pending: usize,
+    wakers: HashMap<u64, Waker>,
+    next_waker: u64,
+}
+
+pub struct NextSettled<'a, T, E> {
+    set: &'a PromiseSet<T, E>,
+    waker: Option<u64>,
+}
+
+impl<T, E> Shared<T, E> {
+    fn poll_next(
+        &self,
+        key: &mut Option<u64>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Settled<T, E>>> {
+        let mut state = self.state.lock().unwrap();
+        let next = match state.ready.pop_front() {
+            Some(settled) => Some(settled),
+            None if state.pending == 0 => None,
+            None => {
+                let key = *key.get_or_insert_with(|| {
+                    state.next_waker += 1;
+                    state.next_waker
+                });
+                state.wakers.insert(key, cx.waker().clone());
+                return Poll::Pending;
+            }
+        };
+        if let Some(key) = key.take() {
+            state.wakers.remove(&key);
+        }
+        Poll::Ready(next)
+    }
+
+    fn forget_waker(&self, key: Option<u64>) {
+        if let Some(key) = key {
+            self.state.lock().unwrap().wakers.remove(&key);
+        }
+    }
+}
+
+impl<T, E> Default for PromiseSet<T, E> {
+    fn default() -> Self {
+        PromiseSet {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    ready: VecDeque::new(),
+                    pending: 0,
+                    wakers: HashMap::new(),
+                    next_waker: 0,
+                }),
+                condvar: Condvar::new(),
+            }),
+            waker: None,
+        }
+    }
+}
+
+impl<T, E> PromiseSet<T, E>
+where
+    T: Clone + Send + 'static,
+    E: Clone + Send + 'static,
+{
+    pub fn new() -> PromiseSet<T, E> {
+        PromiseSet::default()
+    }
+
+    pub fn push(&self, promise: &Promise<T, E>) {
+        self.shared.state.lock().unwrap().pending += 1;
+        let shared = self.shared.clone();
+        promise.on_settled(move |settled| {
+            let mut state = shared.state.lock().unwrap();
+            state.pending -= 1;
+            state.ready.push_back(settled);
+            let wakers = std::mem::take(&mut state.wakers);
+            drop(state);
+            shared.condvar.notify_one();
+            wakers.into_values().for_each(Waker::wake);
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        let state = self.shared.state.lock().unwrap();
+        state.pending + state.ready.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn try_next(&self) -> Option<Settled<T, E>> {
+        self.shared.state.lock().unwrap().ready.pop_front()
+    }
+
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Settled<T, E>>> {
+        self.shared.poll_next(&mut self.waker, cx)
+    }
+
+    pub fn next_settled(&self) -> NextSettled<'_, T, E> {
+        NextSettled {
+            set: self,
+            waker: None,
+        }
+    }
+}
+
+impl<T, E> Iterator for PromiseSet<T, E>
+where
+    T: Clone + Send + 'static,
+    E: Clone + Send + 'static,
+{
+    type Item = Settled<T, E>;
+
+    fn next(&mut self) -> Option<Settled<T, E>> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(settled) = state.ready.pop_front() {
+                return Some(settled);
+            }
+            if state.pending == 0 {
+                return None;
+            }
+            state = self.shared.condvar.wait(state).unwrap();
+        }
+    }
+}
+
+impl<T, E> Future for NextSettled<'_, T, E>
+where
+    T: Clone + Send + 'static,
+    E: Clone + Send + 'static,
+{
+    type Output = Option<Settled<T, E>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let next = self.get_mut();
+        next.set.shared.poll_next(&mut next.waker, cx)
+    }
+}
+
+impl<T, E> Drop for NextSettled<'_, T, E> {
+    fn drop(&mut self) {
+        self.set.shared.forget_waker(self.waker.take());
+    }
+}
+
+impl<T, E> Drop for PromiseSet<T, E> {
+    fn drop(&mut self) {
+        self.shared.forget_waker(self.waker.take());
+    }
+}
+
+impl<T, E> FromIterator<Promise<T, E>> for PromiseSet<T, E>
+where
+    T: Clone + Send + 'static,
+    E: Clone + Send + 'static,
+{
+    fn from_iter<I: IntoIterator<Item = Promise<T, E>>>(promises: I) -> Self {
+        let set = PromiseSet::new();
+        for promise in promises {
+            set.push(&promise);
+        }
+        set
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::task::Wake;
+
+    use super::*;
+
+    struct Flag(Mutex<bool>);
+
+    impl Wake for Flag {
+        fn wake(self: Arc<Self>) {
+            *self.0.lock().unwrap() = true;
+        }
+    }
+
+    #[test]
+    fn yields_outcomes_in_completion_order() {
+        let (first, first_resolver, _) = Promise::<u8, String>::with_resolvers();
+        let (second, _, second_rejecter) = Promise::<u8, String>::with_resolvers();
+        let set: PromiseSet<u8, String> = [first, second].into_iter().collect();
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.try_next(), None);
+        second_rejecter.reject("second".to_string());
+        first_resolver.resolve(1);
+        let outcomes: Vec<_> = set.collect();
+        let expected = vec![
+            Settled::Rejected("second".to_string()),
+            Settled::Fulfilled(1),
+        ];
+        assert_eq!(outcomes, expected);
+    }
+
+    #[test]
+    fn polling_wakes_on_the_next_settlement() {
+        let mut set = PromiseSet::new();
+        let (promise, resolver, _) = Promise::<u8, String>::with_resolvers();
+        set.push(&promise);
+        let flag = Arc::new(Flag(Mutex::new(false)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut next = set.next_settled();
+        assert!(Pin::new(&mut next).poll(&mut cx).is_pending());
+        resolver.resolve(3);
+        assert!(*flag.0.lock().unwrap());
+        assert_eq!(
+            Pin::new(&mut next).poll(&mut cx),
+            Poll::Ready(Some(Settled::Fulfilled(3)))
+        );
+        drop(next);
+        assert_eq!(set.poll_next(&mut cx), Poll::Ready(None));
+        assert!(set.is_empty());
+    }
+
+    #[test]
+    fn concurrent_waiters_are_all_woken() {
+        let (first, first_resolver, _) = Promise::<u8, String>::with_resolvers();
+        let (second, second_resolver, _) = Promise::<u8, String>::with_resolvers();
+        let set: PromiseSet<u8, String> = [first, second].into_iter().collect();
+        let flags = [
+            Arc::new(Flag(Mutex::new(false))),
+            Arc::new(Flag(Mutex::new(false))),
+        ];
+        let wakers = flags.clone().map(Waker::from);
+        let mut waiters = [set.next_settled(), set.next_settled()];
+        for (waiter, waker) in waiters.iter_mut().zip(&wakers) {
+            let mut cx = Context::from_waker(waker);
+            assert!(Pin::new(waiter).poll(&mut cx).is_pending());
+        }
+        first_resolver.resolve(1);
+        assert!(flags.iter().all(|flag| *flag.0.lock().unwrap()));
+
+        let [mut early, mut late] = waiters;
+        let mut cx = Context::from_waker(&wakers[0]);
+        assert_eq!(
+            Pin::new(&mut early).poll(&mut cx),
+            Poll::Ready(Some(Settled::Fulfilled(1)))
+        );
+        *flags[1].0.lock().unwrap() = false;
+        let mut cx = Context::from_waker(&wakers[1]);
+        assert!(Pin::new(&mut late).poll(&mut cx).is_pending());
+        second_resolver.resolve(2);
+        assert!(*flags[1].0.lock().unwrap());
+        assert_eq!(
+            Pin::new(&mut late).poll(&mut cx),
+            Poll::Ready(Some(Settled::Fulfilled(2)))
+        );
+        assert!(set.shared.state.lock().unwrap().wakers.is_empty());
+    }
+}
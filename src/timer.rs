@@ -0,0 +1,231 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::executor::Task;
+use crate::{CancellationToken, Cancelled, Promise};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct TimerId(u64);
+
+pub(crate) struct Timer {
+    state: Mutex<State>,
+    condvar: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    deadlines: BinaryHeap<Reverse<(Instant, u64)>>,
+    callbacks: HashMap<u64, Task>,
+}
+
+// All timers share a single thread that sleeps until the earliest deadline
+// and runs expired callbacks itself. Callbacks must therefore be short and
+// never block; anything else has to go through `executor::spawn`.
+pub(crate) fn timer() -> &'static Timer {
+    static TIMER: OnceLock<&'static Timer> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        let timer: &'static Timer = Box::leak(Box::new(Timer {
+            state: Mutex::new(State::default()),
+            condvar: Condvar::new(),
+        }));
+        thread::Builder::new()
+            .name("promise-timer".to_string())
+            .spawn(move || timer.run())
+            .expect("failed to spawn the promise timer thread");
+        timer
+    })
+}
+
+impl Timer {
+    pub(crate) fn schedule<F>(&self, deadline: Instant, callback: F) -> TimerId
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.deadlines.push(Reverse((deadline, id)));
+        state.callbacks.insert(id, Box::new(callback));
+        self.condvar.notify_one();
+        TimerId(id)
+    }
+
+    pub(crate) fn cancel(&self, id: TimerId) {
+        let mut state = self.state.lock().unwrap();
+        state.callbacks.remove(&id.0);
+        if state.deadlines.len() > 2 * state.callbacks.len() + 64 {
+            let State {
+                deadlines,
+                callbacks,
+                ..
+            } = &mut *state;
+            deadlines.retain(|Reverse((_, id))| callbacks.contains_key(id));
+        }
+    }
+
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let Some(&Reverse((deadline, id))) = state.deadlines.peek() else {
+                state = self.condvar.wait(state).unwrap();
+                continue;
+            };
+            let now = Instant::now();
+            if deadline > now {
+                state = self.condvar.wait_timeout(state, deadline - now).unwrap().0;
+                continue;
+            }
+            state.deadlines.pop();
+            if let Some(callback) = state.callbacks.remove(&id) {
+                drop(state);
+                callback();
+                state = self.state.lock().unwrap();
+            }
+        }
+    }
+}
+
+pub(crate) fn fire_at<T, E>(deadline: Instant, token: CancellationToken, value: T) -> Promise<T, E>
+where
+    T: Clone + Send + 'static,
+    E: Clone + Send + 'static,
+{
+    let promise = Promise::pending_with(token);
+    let promise_elapsed = promise.clone();
+    let id = timer().schedule(deadline, move || promise_elapsed.settle_detached(Ok(value)));
+    promise.token().on_cancel(move || timer().cancel(id));
+    promise
+}
+
+pub struct Interval {
+    period: Duration,
+    next: Mutex<Instant>,
+    token: CancellationToken,
+}
+
+impl Interval {
+    pub fn new(period: Duration) -> Interval {
+        assert!(period > Duration::ZERO, "interval period must be non-zero");
+        Interval {
+            period,
+            next: Mutex::new(Instant::now() + period),
+            token: CancellationToken::new(),
+        }
+    }
+
+    // Resolves with the scheduled instant of the next tick. Ticks are laid out
+    // on a fixed grid, so a slow consumer gets the missed ticks back to back.
+    pub fn tick(&self) -> Promise<Instant, Cancelled> {
+        let deadline = {
+            let mut next = self.next.lock().unwrap();
+            let deadline = *next;
+            *next += self.period;
+            deadline
+        };
+        fire_at(deadline, self.token.child(), deadline)
+    }
+
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+}
+
+impl Iterator for Interval {
+    type Item = Instant;
+
+    fn next(&mut self) -> Option<Instant> {
+        self.tick().a_await().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{default_executor, TimedOut};
+
+    // Occupies every worker of the default pool until the sender is dropped.
+    fn saturate_pool() -> mpsc::Sender<()> {
+        let (release, blocked) = mpsc::channel::<()>();
+        let blocked = Arc::new(Mutex::new(blocked));
+        for _ in 0..default_executor().workers() {
+            let blocked = blocked.clone();
+            Promise::<(), String>::new(move |resolve, _| {
+                let _ = blocked.lock().unwrap().recv();
+                resolve(());
+            });
+        }
+        release
+    }
+
+    #[test]
+    fn timeout_fires_while_the_pool_is_saturated() {
+        let release = saturate_pool();
+        let (never, _, _) = Promise::<(), String>::with_resolvers();
+        let outcome = never
+            .timeout(Duration::from_millis(50))
+            .wait_timeout(Duration::from_secs(2));
+        drop(release);
+        assert_eq!(outcome, Ok(Err(TimedOut.to_string())));
+    }
+
+    #[test]
+    fn cancelled_timers_never_fire() {
+        let (fired, receiver) = mpsc::channel();
+        let cancelled = fired.clone();
+        let id = timer().schedule(Instant::now() + Duration::from_millis(20), move || {
+            let _ = cancelled.send("cancelled");
+        });
+        timer().cancel(id);
+        timer().schedule(Instant::now() + Duration::from_millis(40), move || {
+            let _ = fired.send("fired");
+        });
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(2)), Ok("fired"));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn delays_inside_every_worker_complete() {
+        let workers = default_executor().workers();
+        let promises = (0..workers)
+            .map(|index| {
+                Promise::<usize, String>::new(move |resolve, _| {
+                    let delay = Promise::<(), Cancelled>::delay(Duration::from_millis(10));
+                    delay.a_await().unwrap();
+                    resolve(index);
+                })
+            })
+            .collect();
+        let outcome = Promise::all(promises).wait_timeout(Duration::from_secs(3));
+        assert_eq!(outcome, Ok(Ok((0..workers).collect())));
+    }
+
+    #[test]
+    fn delay_is_cancelled_with_its_token() {
+        let delay = Promise::<(), Cancelled>::delay(Duration::from_secs(60));
+        delay.cancel();
+        assert_eq!(delay.a_await(), Err(Cancelled));
+    }
+
+    #[test]
+    fn interval_ticks_on_a_fixed_grid() {
+        let period = Duration::from_millis(5);
+        let mut interval = Interval::new(period);
+        let first = interval.next().unwrap();
+        let second = interval.next().unwrap();
+        assert_eq!(second - first, period);
+        interval.cancel();
+        assert!(interval.is_cancelled());
+        assert_eq!(interval.next(), None);
+    }
+}